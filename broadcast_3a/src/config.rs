@@ -0,0 +1,192 @@
+use std::{env, str::FromStr, time::Duration};
+
+use broadcast_3a::swim::SwimConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Periodic anti-entropy gossip of everything a neighbour lacks.
+    Gossip,
+    /// Eager push along a spanning tree, lazy `ihave` to everyone else.
+    Plumtree,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gossip" => Ok(Strategy::Gossip),
+            "plumtree" => Ok(Strategy::Plumtree),
+            _ => Err(format!("unknown broadcast strategy: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Membership {
+    /// Neighbours come from the `topology` message, or all nodes from `init`.
+    Static,
+    /// Neighbours are the HyParView active view.
+    HyParView,
+}
+
+impl FromStr for Membership {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "static" => Ok(Membership::Static),
+            "hyparview" => Ok(Membership::HyParView),
+            _ => Err(format!("unknown membership: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    /// Integers, as in the Maelstrom broadcast workload.
+    Integer,
+    /// Any JSON value.
+    Json,
+}
+
+impl FromStr for ValueType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "integer" => Ok(ValueType::Integer),
+            "json" => Ok(ValueType::Json),
+            _ => Err(format!("unknown value type: {}", s)),
+        }
+    }
+}
+
+/// Runtime knobs, read from `BROADCAST_*` environment variables so the same
+/// binary can be pointed at different Maelstrom runs without rebuilding.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub strategy: Strategy,
+    pub membership: Membership,
+    /// What clients broadcast.
+    pub value_type: ValueType,
+    /// How often the main loop wakes up to run timers.
+    pub tick_interval: Duration,
+    /// How often anti-entropy gossip is sent to neighbours, unless adaptive.
+    pub gossip_interval: Duration,
+    /// Derive the gossip interval and batch size from observed load.
+    pub adaptive: bool,
+    /// Time within which a broadcast value should reach every node.
+    pub target_latency: Duration,
+    /// Messages the cluster may spend per client broadcast.
+    pub target_msgs_per_op: f64,
+    pub min_gossip_interval: Duration,
+    pub max_gossip_interval: Duration,
+    /// Smallest number of values a gossip message is allowed to carry.
+    pub min_batch_size: u64,
+    /// How long to wait for an announced value before grafting the announcer.
+    pub graft_timeout: Duration,
+    /// Maximum size of the HyParView active view.
+    pub active_view_size: usize,
+    /// Maximum size of the HyParView passive view.
+    pub passive_view_size: usize,
+    /// How often views are shuffled with a random walk.
+    pub shuffle_interval: Duration,
+    /// How long an active peer may leave a request unanswered before it is
+    /// considered failed.
+    pub failure_timeout: Duration,
+    /// Run the SWIM failure detector.
+    pub swim: bool,
+    pub swim_config: SwimConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            strategy: Strategy::Gossip,
+            membership: Membership::Static,
+            value_type: ValueType::Integer,
+            tick_interval: Duration::from_millis(50),
+            gossip_interval: Duration::from_millis(100),
+            adaptive: true,
+            target_latency: Duration::from_millis(500),
+            target_msgs_per_op: 20.0,
+            min_gossip_interval: Duration::from_millis(20),
+            max_gossip_interval: Duration::from_millis(1000),
+            min_batch_size: 100,
+            graft_timeout: Duration::from_millis(200),
+            active_view_size: 4,
+            passive_view_size: 12,
+            shuffle_interval: Duration::from_millis(1000),
+            failure_timeout: Duration::from_millis(1000),
+            swim: false,
+            swim_config: SwimConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let mut config = Config::default();
+
+        if let Some(strategy) = read_env("BROADCAST_STRATEGY") {
+            config.strategy = strategy;
+            if config.strategy == Strategy::Plumtree {
+                // the tree does the dissemination, gossip only heals partitions
+                config.gossip_interval = Duration::from_millis(1000);
+                config.adaptive = false;
+            }
+        }
+        if let Some(membership) = read_env("BROADCAST_MEMBERSHIP") {
+            config.membership = membership;
+        }
+        if let Some(value_type) = read_env("BROADCAST_VALUE_TYPE") {
+            config.value_type = value_type;
+        }
+        if let Some(ms) = read_env("BROADCAST_GOSSIP_INTERVAL_MS") {
+            // a fixed interval was asked for
+            config.gossip_interval = Duration::from_millis(ms);
+            config.adaptive = false;
+        }
+        if let Some(adaptive) = read_env("BROADCAST_ADAPTIVE") {
+            config.adaptive = adaptive;
+        }
+        if let Some(ms) = read_env("BROADCAST_TARGET_LATENCY_MS") {
+            config.target_latency = Duration::from_millis(ms);
+        }
+        if let Some(msgs) = read_env("BROADCAST_TARGET_MSGS_PER_OP") {
+            config.target_msgs_per_op = msgs;
+        }
+        if let Some(ms) = read_env("BROADCAST_GRAFT_TIMEOUT_MS") {
+            config.graft_timeout = Duration::from_millis(ms);
+        }
+        if let Some(size) = read_env("BROADCAST_ACTIVE_VIEW_SIZE") {
+            config.active_view_size = size;
+        }
+        if let Some(size) = read_env("BROADCAST_PASSIVE_VIEW_SIZE") {
+            config.passive_view_size = size;
+        }
+        if let Some(ms) = read_env("BROADCAST_FAILURE_TIMEOUT_MS") {
+            config.failure_timeout = Duration::from_millis(ms);
+        }
+        if let Some(swim) = read_env("BROADCAST_SWIM") {
+            config.swim = swim;
+        }
+        if let Some(ms) = read_env("BROADCAST_PROTOCOL_PERIOD_MS") {
+            config.swim_config.protocol_period = Duration::from_millis(ms);
+        }
+        if let Some(ms) = read_env("BROADCAST_SUSPICION_TIMEOUT_MS") {
+            config.swim_config.suspicion_timeout = Duration::from_millis(ms);
+        }
+
+        config
+    }
+}
+
+fn read_env<T: FromStr>(key: &str) -> Option<T> {
+    let value = env::var(key).ok()?;
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => panic!("invalid value for {}: {}", key, value),
+    }
+}
@@ -0,0 +1,202 @@
+use broadcast_3a::swim::{MemberUpdate, Outgoing};
+
+use crate::{
+    node::Node,
+    packet::{Message, RequestBody},
+    value::Value,
+};
+
+/// Runs the node's `Swim` over the broadcast protocol's messages.
+pub trait FailureDetector<V: Value> {
+    fn on_ping(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_ping_ok(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_ping_req(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_ping_req_ok(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn swim_tick(&mut self) -> Vec<Message<V>>;
+}
+
+impl<V: Value> Node<V> {
+    pub(crate) fn apply_updates(&mut self, updates: Vec<MemberUpdate>) {
+        self.swim.apply(updates);
+    }
+
+    fn swim_message(&mut self, outgoing: Outgoing) -> Message<V> {
+        let (dest, body) = match outgoing {
+            Outgoing::Ping {
+                dest,
+                msg_id,
+                updates,
+            } => (dest, RequestBody::Ping { msg_id, updates }),
+            Outgoing::PingOk {
+                dest,
+                in_reply_to,
+                updates,
+            } => (
+                dest,
+                RequestBody::PingOk {
+                    msg_id: in_reply_to,
+                    in_reply_to,
+                    updates,
+                },
+            ),
+            Outgoing::PingReq {
+                dest,
+                msg_id,
+                target,
+                updates,
+            } => (
+                dest,
+                RequestBody::PingReq {
+                    msg_id,
+                    target,
+                    updates,
+                },
+            ),
+            Outgoing::PingReqOk {
+                dest,
+                in_reply_to,
+                target,
+                updates,
+            } => (
+                dest,
+                RequestBody::PingReqOk {
+                    msg_id: in_reply_to,
+                    in_reply_to,
+                    target,
+                    updates,
+                },
+            ),
+        };
+
+        Message {
+            src: self.id.clone(),
+            dest,
+            body,
+        }
+    }
+}
+
+impl<V: Value> FailureDetector<V> for Node<V> {
+    fn on_ping(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::Ping { msg_id, updates } => {
+                let outgoing = self.swim.on_ping(&message.src, msg_id, updates);
+                vec![self.swim_message(outgoing)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_ping_ok(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::PingOk {
+                in_reply_to,
+                updates,
+                ..
+            } => self
+                .swim
+                .on_ping_ok(&message.src, in_reply_to, updates)
+                .map(|outgoing| self.swim_message(outgoing))
+                .into_iter()
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    fn on_ping_req(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::PingReq {
+                msg_id,
+                target,
+                updates,
+            } => {
+                let outgoing =
+                    self.swim
+                        .on_ping_req(&message.src, msg_id, target, updates, &mut || {
+                            self.next_msg_id += 1;
+                            self.next_msg_id
+                        });
+                vec![self.swim_message(outgoing)]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_ping_req_ok(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::PingReqOk {
+                target, updates, ..
+            } => {
+                self.swim.on_ping_req_ok(&target, updates);
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn swim_tick(&mut self) -> Vec<Message<V>> {
+        let outgoing = self.swim.tick(&self.config.swim_config, &mut || {
+            self.next_msg_id += 1;
+            self.next_msg_id
+        });
+
+        outgoing
+            .into_iter()
+            .map(|outgoing| self.swim_message(outgoing))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use broadcast_3a::swim::Health;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        node::tests::{message, node},
+    };
+
+    fn swim_node(id: &str) -> Node<u64> {
+        let config = Config {
+            swim: true,
+            ..Config::default()
+        };
+        node(id, 3, config)
+    }
+
+    #[test]
+    fn ping_is_answered_with_the_pinged_msg_id() {
+        let mut n1 = swim_node("n1");
+
+        let ping = RequestBody::Ping {
+            msg_id: 7,
+            updates: vec![],
+        };
+        let sent = n1.on_ping(message("n0", "n1", ping));
+
+        let [Message {
+            dest,
+            body: RequestBody::PingOk { in_reply_to, .. },
+            ..
+        }] = &sent[..]
+        else {
+            panic!("expected a ping_ok, got {:?}", sent);
+        };
+        assert_eq!(dest, "n0");
+        assert_eq!(*in_reply_to, 7);
+    }
+
+    #[test]
+    fn dead_peers_are_left_out_of_gossip() {
+        let mut n0 = swim_node("n0");
+
+        n0.apply_updates(vec![MemberUpdate {
+            node: "n1".to_string(),
+            health: Health::Dead,
+            incarnation: 0,
+        }]);
+
+        assert_eq!(n0.neighbours(), vec!["n2".to_string()]);
+    }
+}
@@ -1,17 +1,22 @@
 use crate::{
     node::Node,
     packet::{Message, RequestBody},
+    value::{Value, ValueSet},
 };
 
-pub trait Gossip {
-    fn on_topology(&mut self, message: Message) -> Option<Message>;
-    fn on_read(&mut self, message: Message) -> Option<Message>;
-    fn on_broadcast(&mut self, message: Message) -> Option<Message>;
-    fn on_error(&mut self, message: Message) -> Option<Message>;
+pub trait Gossip<V: Value> {
+    fn on_topology(&mut self, message: Message<V>) -> Option<Message<V>>;
+    fn on_read(&mut self, message: Message<V>) -> Option<Message<V>>;
+    fn on_read_since(&mut self, message: Message<V>) -> Option<Message<V>>;
+    fn on_broadcast(&mut self, message: Message<V>) -> Option<Message<V>>;
+    fn on_gossip(&mut self, message: Message<V>) -> Option<Message<V>>;
+    fn on_gossip_ok(&mut self, message: Message<V>) -> Option<Message<V>>;
+    fn on_error(&mut self, message: Message<V>) -> Option<Message<V>>;
+    fn gossip(&mut self) -> Vec<Message<V>>;
 }
 
-impl Gossip for Node {
-    fn on_topology(&mut self, message: Message) -> Option<Message> {
+impl<V: Value> Gossip<V> for Node<V> {
+    fn on_topology(&mut self, message: Message<V>) -> Option<Message<V>> {
         match message.body {
             RequestBody::Topology {
                 msg_id, topology, ..
@@ -33,7 +38,7 @@ impl Gossip for Node {
         }
     }
 
-    fn on_read(&mut self, message: Message) -> Option<Message> {
+    fn on_read(&mut self, message: Message<V>) -> Option<Message<V>> {
         match message.body {
             RequestBody::Read { msg_id } => {
                 let to_send = Message {
@@ -52,13 +57,38 @@ impl Gossip for Node {
         }
     }
 
-    fn on_broadcast(&mut self, message: Message) -> Option<Message> {
+    fn on_read_since(&mut self, message: Message<V>) -> Option<Message<V>> {
+        match message.body {
+            RequestBody::ReadSince { msg_id, cursor } => {
+                let (messages, cursor) = self.storage.get_messages_since(cursor);
+
+                let to_send = Message {
+                    src: self.id.clone(),
+                    dest: message.src,
+                    body: RequestBody::ReadSinceOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        messages,
+                        cursor,
+                    },
+                };
+
+                Some(to_send)
+            }
+            _ => None,
+        }
+    }
+
+    fn on_broadcast(&mut self, message: Message<V>) -> Option<Message<V>> {
         match message.body {
             RequestBody::Broadcast {
                 msg_id,
                 message: msg,
             } => {
-                self.storage.add_message(msg);
+                self.pacer.record_broadcast();
+                if self.storage.add_message(msg) {
+                    self.pacer.record_new_values(1);
+                }
 
                 let to_send = Message {
                     src: self.id.clone(),
@@ -75,7 +105,55 @@ impl Gossip for Node {
         }
     }
 
-    fn on_error(&mut self, message: Message) -> Option<Message> {
+    fn on_gossip(&mut self, message: Message<V>) -> Option<Message<V>> {
+        match message.body {
+            RequestBody::Gossip {
+                msg_id,
+                messages,
+                updates,
+            } => {
+                self.apply_updates(updates);
+                let fresh = messages.difference(&self.storage.messages);
+                self.pacer.record_new_values(fresh.len());
+                self.storage.add_messages(&fresh);
+                self.storage.mark_known(&message.src, &messages);
+
+                let to_send = Message {
+                    src: self.id.clone(),
+                    dest: message.src,
+                    body: RequestBody::GossipOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        updates: self.swim.piggyback(),
+                    },
+                };
+
+                Some(to_send)
+            }
+            _ => None,
+        }
+    }
+
+    fn on_gossip_ok(&mut self, message: Message<V>) -> Option<Message<V>> {
+        match message.body {
+            RequestBody::GossipOk {
+                in_reply_to,
+                updates,
+                ..
+            } => {
+                self.pacer.record_ack(in_reply_to);
+                self.apply_updates(updates);
+                if let Some(messages) = self.storage.in_flight.remove(&in_reply_to) {
+                    self.storage.mark_known(&message.src, &messages);
+                }
+
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn on_error(&mut self, message: Message<V>) -> Option<Message<V>> {
         match message.body {
             RequestBody::Error {
                 text,
@@ -97,4 +175,144 @@ impl Gossip for Node {
             _ => None,
         }
     }
+
+    fn gossip(&mut self) -> Vec<Message<V>> {
+        let mut to_send = Vec::new();
+        let batch_size = self.gossip_batch_size();
+
+        for neighbour in self.neighbours() {
+            let messages = self.storage.unknown_to(&neighbour).truncated(batch_size);
+            if messages.is_empty() {
+                continue;
+            }
+
+            let msg_id = self.msg_id();
+            self.storage.in_flight.insert(msg_id, messages.clone());
+            to_send.push(Message {
+                src: self.id.clone(),
+                dest: neighbour,
+                body: RequestBody::Gossip {
+                    msg_id,
+                    messages,
+                    updates: self.swim.piggyback(),
+                },
+            });
+        }
+
+        to_send
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        config::Config,
+        message_set::MessageSet,
+        node::tests::{message, node, set},
+    };
+
+    /// n0 of three nodes, gossiping on every tick.
+    fn gossiping_node() -> Node<u64> {
+        let config = Config {
+            adaptive: false,
+            gossip_interval: Duration::ZERO,
+            ..Config::default()
+        };
+        node("n0", 3, config)
+    }
+
+    /// What each gossip sent carries, by destination and msg_id.
+    fn gossiped(sent: &[Message<u64>]) -> Vec<(&str, u64, &MessageSet)> {
+        sent.iter()
+            .filter_map(|sent| match &sent.body {
+                RequestBody::Gossip {
+                    msg_id, messages, ..
+                } => Some((sent.dest.as_str(), *msg_id, messages)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn ack(src: &str, in_reply_to: u64) -> Message<u64> {
+        let body = RequestBody::GossipOk {
+            msg_id: in_reply_to,
+            in_reply_to,
+            updates: vec![],
+        };
+        message(src, "n0", body)
+    }
+
+    #[test]
+    fn acknowledged_values_are_not_gossiped_to_that_neighbour_again() {
+        let mut n0 = gossiping_node();
+        n0.storage.add_messages(&set(&[1, 2]));
+
+        let sent = n0.on_tick();
+        let gossip = gossiped(&sent);
+        assert_eq!(gossip.len(), 2);
+        let (_, to_n1, _) = gossip.iter().find(|(dest, ..)| *dest == "n1").unwrap();
+        n0.on_gossip_ok(ack("n1", *to_n1));
+
+        n0.storage.add_message(3);
+        let sent = n0.on_tick();
+        let gossip = gossiped(&sent);
+        let sent_to = |peer| {
+            gossip
+                .iter()
+                .find(|(dest, ..)| *dest == peer)
+                .map(|(_, _, messages)| (*messages).clone())
+        };
+        assert_eq!(sent_to("n1"), Some(set(&[3])));
+        assert_eq!(sent_to("n2"), Some(set(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn values_a_neighbour_sent_are_not_gossiped_back() {
+        let mut n0 = gossiping_node();
+        let gossip = RequestBody::Gossip {
+            msg_id: 1,
+            messages: set(&[1]),
+            updates: vec![],
+        };
+        n0.on_gossip(message("n1", "n0", gossip));
+
+        let sent = n0.on_tick();
+
+        let gossip = gossiped(&sent);
+        assert_eq!(gossip.len(), 1);
+        assert_eq!(gossip[0].0, "n2");
+    }
+
+    #[test]
+    fn unacknowledged_values_are_sent_again_next_round() {
+        let mut n0 = gossiping_node();
+        n0.storage.add_messages(&set(&[1, 2]));
+
+        let first = n0.on_tick();
+        let second = n0.on_tick();
+
+        for peer in ["n1", "n2"] {
+            let [(_, first_id, first), (_, second_id, second)] = [&first, &second].map(|sent| {
+                gossiped(sent)
+                    .into_iter()
+                    .find(|(dest, ..)| *dest == peer)
+                    .expect("gossiped every round")
+            });
+            assert_eq!(*first, set(&[1, 2]));
+            assert_eq!(first, second);
+            assert_ne!(first_id, second_id);
+        }
+
+        // a late ack for the first round still counts
+        let (_, first_to_n1, _) = gossiped(&first)
+            .into_iter()
+            .find(|(dest, ..)| *dest == "n1")
+            .unwrap();
+        n0.on_gossip_ok(ack("n1", first_to_n1));
+        let third = n0.on_tick();
+        assert!(gossiped(&third).iter().all(|(dest, ..)| *dest != "n1"));
+    }
 }
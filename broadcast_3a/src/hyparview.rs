@@ -0,0 +1,664 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Instant,
+};
+
+use broadcast_3a::swim::Health;
+use rand::seq::IteratorRandom;
+
+use crate::{
+    node::Node,
+    packet::{Message, RequestBody},
+    value::Value,
+};
+
+/// Random walk length for `forward_join`.
+const ACTIVE_RANDOM_WALK_LENGTH: u64 = 6;
+/// Hop at which a `forward_join` also adds the joiner to the passive view.
+const PASSIVE_RANDOM_WALK_LENGTH: u64 = 3;
+/// How many active and passive view entries go into a shuffle.
+const SHUFFLE_ACTIVE: usize = 3;
+const SHUFFLE_PASSIVE: usize = 4;
+
+/// Partial-view membership (Leitão et al., "HyParView").
+///
+/// The active view is a small symmetric set of peers that broadcast traffic
+/// flows over. The passive view is a larger pool of nodes kept fresh by
+/// periodic shuffles and used to replace active peers that fail.
+#[derive(Debug, Default)]
+pub struct HyParView {
+    pub(crate) active: BTreeSet<String>,
+    pub(crate) passive: BTreeSet<String>,
+    /// Passive node asked to become an active neighbour, and when.
+    pending_neighbor: Option<(String, Instant)>,
+    /// Active peers that owe us a reply, with the time of the oldest request.
+    awaiting: HashMap<String, Instant>,
+    last_shuffle: Option<Instant>,
+}
+
+impl HyParView {
+    /// Records that `peer` has a request outstanding from us.
+    pub(crate) fn expect_reply(&mut self, peer: &str) {
+        if self.active.contains(peer) {
+            self.awaiting
+                .entry(peer.to_string())
+                .or_insert_with(Instant::now);
+        }
+    }
+
+    /// Any message from `peer` proves it is still alive.
+    pub(crate) fn heard_from(&mut self, peer: &str) {
+        self.awaiting.remove(peer);
+    }
+
+    fn add_passive(&mut self, node: &str, me: &str, capacity: usize) {
+        if node == me || self.active.contains(node) || self.passive.contains(node) {
+            return;
+        }
+        if self.passive.len() >= capacity {
+            let evicted = self.passive.iter().choose(&mut rand::thread_rng()).cloned();
+            if let Some(evicted) = evicted {
+                self.passive.remove(&evicted);
+            }
+        }
+        self.passive.insert(node.to_string());
+    }
+
+    fn random_active_except(&self, except: &str) -> Option<String> {
+        self.active
+            .iter()
+            .filter(|peer| *peer != except)
+            .choose(&mut rand::thread_rng())
+            .cloned()
+    }
+}
+
+pub trait Membership<V: Value> {
+    fn join(&mut self) -> Vec<Message<V>>;
+    fn on_join(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_forward_join(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_neighbor(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_neighbor_ok(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_disconnect(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_shuffle(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_shuffle_ok(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn membership_tick(&mut self) -> Vec<Message<V>>;
+}
+
+impl<V: Value> Node<V> {
+    /// Moves `peer` into the active view, evicting a random active peer to
+    /// the passive view if there is no room.
+    fn add_active(&mut self, peer: &str) -> Vec<Message<V>> {
+        if peer == self.id || self.hyparview.active.contains(peer) {
+            return vec![];
+        }
+
+        let mut to_send = Vec::new();
+        if self.hyparview.active.len() >= self.config.active_view_size {
+            let evicted = self
+                .hyparview
+                .active
+                .iter()
+                .choose(&mut rand::thread_rng())
+                .cloned();
+            if let Some(evicted) = evicted {
+                self.drop_active(&evicted);
+                to_send.push(Message {
+                    src: self.id.clone(),
+                    dest: evicted,
+                    body: RequestBody::Disconnect {
+                        msg_id: self.msg_id(),
+                    },
+                });
+            }
+        }
+
+        self.hyparview.passive.remove(peer);
+        self.hyparview.active.insert(peer.to_string());
+        self.plumtree.neighbour_up(peer);
+
+        to_send
+    }
+
+    /// Moves `peer` from the active to the passive view.
+    fn drop_active(&mut self, peer: &str) {
+        if self.hyparview.active.remove(peer) {
+            self.hyparview.awaiting.remove(peer);
+            self.plumtree.neighbour_down(peer);
+            let me = self.id.clone();
+            let capacity = self.config.passive_view_size;
+            self.hyparview.add_passive(peer, &me, capacity);
+        }
+    }
+
+    fn add_passive(&mut self, node: &str) {
+        let me = self.id.clone();
+        let capacity = self.config.passive_view_size;
+        self.hyparview.add_passive(node, &me, capacity);
+    }
+
+    fn join_through(&mut self, contact: &str) -> Vec<Message<V>> {
+        let mut to_send = self.add_active(contact);
+        to_send.push(Message {
+            src: self.id.clone(),
+            dest: contact.to_string(),
+            body: RequestBody::Join {
+                msg_id: self.msg_id(),
+            },
+        });
+
+        to_send
+    }
+
+    fn shuffle_sample(&self) -> Vec<String> {
+        let mut rng = rand::thread_rng();
+        let mut nodes = vec![self.id.clone()];
+        nodes.extend(
+            self.hyparview
+                .active
+                .iter()
+                .cloned()
+                .choose_multiple(&mut rng, SHUFFLE_ACTIVE),
+        );
+        nodes.extend(
+            self.hyparview
+                .passive
+                .iter()
+                .cloned()
+                .choose_multiple(&mut rng, SHUFFLE_PASSIVE),
+        );
+        nodes
+    }
+}
+
+impl<V: Value> Membership<V> for Node<V> {
+    /// Joins the overlay through the first node of the cluster.
+    fn join(&mut self) -> Vec<Message<V>> {
+        match self.peers.iter().min() {
+            Some(contact) if *contact != self.id => {
+                let contact = contact.clone();
+                self.join_through(&contact)
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_join(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::Join { .. } => {
+                let joiner = message.src;
+                let mut to_send = self.add_active(&joiner);
+
+                let others: Vec<String> = self
+                    .hyparview
+                    .active
+                    .iter()
+                    .filter(|peer| **peer != joiner)
+                    .cloned()
+                    .collect();
+                for peer in others {
+                    to_send.push(Message {
+                        src: self.id.clone(),
+                        dest: peer,
+                        body: RequestBody::ForwardJoin {
+                            msg_id: self.msg_id(),
+                            node: joiner.clone(),
+                            ttl: ACTIVE_RANDOM_WALK_LENGTH,
+                        },
+                    });
+                }
+
+                to_send
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_forward_join(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::ForwardJoin { node, ttl, .. } => {
+                if node == self.id {
+                    return vec![];
+                }
+
+                if ttl == 0 || self.hyparview.active.len() <= 1 {
+                    let mut to_send = self.add_active(&node);
+                    to_send.push(Message {
+                        src: self.id.clone(),
+                        dest: node,
+                        body: RequestBody::Neighbor {
+                            msg_id: self.msg_id(),
+                            high_priority: true,
+                        },
+                    });
+                    return to_send;
+                }
+
+                if ttl == PASSIVE_RANDOM_WALK_LENGTH {
+                    self.add_passive(&node);
+                }
+
+                match self.hyparview.random_active_except(&message.src) {
+                    Some(next) => vec![Message {
+                        src: self.id.clone(),
+                        dest: next,
+                        body: RequestBody::ForwardJoin {
+                            msg_id: self.msg_id(),
+                            node,
+                            ttl: ttl - 1,
+                        },
+                    }],
+                    None => vec![],
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_neighbor(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::Neighbor {
+                msg_id,
+                high_priority,
+            } => {
+                let has_room = self.hyparview.active.len() < self.config.active_view_size;
+                let accepted = high_priority || has_room;
+
+                let mut to_send = if accepted {
+                    self.add_active(&message.src)
+                } else {
+                    self.add_passive(&message.src);
+                    vec![]
+                };
+
+                to_send.push(Message {
+                    src: self.id.clone(),
+                    dest: message.src,
+                    body: RequestBody::NeighborOk {
+                        msg_id,
+                        in_reply_to: msg_id,
+                        accepted,
+                    },
+                });
+
+                to_send
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_neighbor_ok(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::NeighborOk { accepted, .. } => {
+                if let Some((pending, _)) = &self.hyparview.pending_neighbor {
+                    if *pending == message.src {
+                        self.hyparview.pending_neighbor = None;
+                    }
+                }
+
+                if accepted {
+                    self.add_active(&message.src)
+                } else {
+                    vec![]
+                }
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_disconnect(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::Disconnect { .. } => {
+                self.drop_active(&message.src);
+
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_shuffle(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::Shuffle {
+                origin, nodes, ttl, ..
+            } => {
+                if ttl > 1 && self.hyparview.active.len() > 1 {
+                    if let Some(next) = self.hyparview.random_active_except(&message.src) {
+                        return vec![Message {
+                            src: self.id.clone(),
+                            dest: next,
+                            body: RequestBody::Shuffle {
+                                msg_id: self.msg_id(),
+                                origin,
+                                nodes,
+                                ttl: ttl - 1,
+                            },
+                        }];
+                    }
+                }
+
+                let reply: Vec<String> = self
+                    .hyparview
+                    .passive
+                    .iter()
+                    .cloned()
+                    .choose_multiple(&mut rand::thread_rng(), nodes.len());
+                for node in &nodes {
+                    self.add_passive(node);
+                }
+
+                if origin == self.id {
+                    return vec![];
+                }
+
+                vec![Message {
+                    src: self.id.clone(),
+                    dest: origin,
+                    body: RequestBody::ShuffleOk {
+                        msg_id: self.msg_id(),
+                        nodes: reply,
+                    },
+                }]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_shuffle_ok(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::ShuffleOk { nodes, .. } => {
+                for node in &nodes {
+                    self.add_passive(node);
+                }
+
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Replaces failed active peers from the passive view and periodically
+    /// shuffles views with a random walk.
+    fn membership_tick(&mut self) -> Vec<Message<V>> {
+        let mut to_send = Vec::new();
+        let failure_timeout = self.config.failure_timeout;
+
+        // with SWIM running it decides who has failed, otherwise an active
+        // peer that leaves a request unanswered for too long is dropped
+        let failed: Vec<String> = if self.config.swim {
+            self.hyparview
+                .active
+                .iter()
+                .filter(|peer| self.health(peer) == Health::Dead)
+                .cloned()
+                .collect()
+        } else {
+            self.hyparview
+                .awaiting
+                .iter()
+                .filter(|(_, since)| since.elapsed() >= failure_timeout)
+                .map(|(peer, _)| peer.clone())
+                .collect()
+        };
+        for peer in failed {
+            self.drop_active(&peer);
+            // a failed node should not be promoted straight back
+            self.hyparview.passive.remove(&peer);
+        }
+
+        if let Some((pending, since)) = &self.hyparview.pending_neighbor {
+            if since.elapsed() >= failure_timeout {
+                let pending = pending.clone();
+                self.hyparview.passive.remove(&pending);
+                self.hyparview.pending_neighbor = None;
+            }
+        }
+
+        let has_room = self.hyparview.active.len() < self.config.active_view_size;
+        if has_room && self.hyparview.pending_neighbor.is_none() {
+            let candidate = self
+                .hyparview
+                .passive
+                .iter()
+                .filter(|peer| self.health(peer) != Health::Dead)
+                .choose(&mut rand::thread_rng())
+                .cloned();
+            if let Some(candidate) = candidate {
+                self.hyparview.pending_neighbor = Some((candidate.clone(), Instant::now()));
+                to_send.push(Message {
+                    src: self.id.clone(),
+                    dest: candidate,
+                    body: RequestBody::Neighbor {
+                        msg_id: self.msg_id(),
+                        high_priority: self.hyparview.active.is_empty(),
+                    },
+                });
+            } else if self.hyparview.active.is_empty() {
+                // isolated with nobody left to ask: join again through any
+                // node from init
+                let contact = self
+                    .peers
+                    .iter()
+                    .filter(|peer| **peer != self.id)
+                    .choose(&mut rand::thread_rng())
+                    .cloned();
+                if let Some(contact) = contact {
+                    self.hyparview.pending_neighbor = Some((contact.clone(), Instant::now()));
+                    to_send.extend(self.join_through(&contact));
+                }
+            }
+        }
+
+        let shuffle_due = self
+            .hyparview
+            .last_shuffle
+            .is_none_or(|at| at.elapsed() >= self.config.shuffle_interval);
+        if shuffle_due {
+            self.hyparview.last_shuffle = Some(Instant::now());
+            if let Some(peer) = self.hyparview.random_active_except(&self.id) {
+                let nodes = self.shuffle_sample();
+                to_send.push(Message {
+                    src: self.id.clone(),
+                    dest: peer,
+                    body: RequestBody::Shuffle {
+                        msg_id: self.msg_id(),
+                        origin: self.id.clone(),
+                        nodes,
+                        ttl: ACTIVE_RANDOM_WALK_LENGTH,
+                    },
+                });
+            }
+        }
+
+        to_send
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, time::Duration};
+
+    use super::*;
+    use crate::{
+        config::{Config, Membership as MembershipKind},
+        node::tests::{message, node},
+    };
+
+    fn overlay_node(id: &str, size: usize) -> Node<u64> {
+        let config = Config {
+            membership: MembershipKind::HyParView,
+            active_view_size: 3,
+            passive_view_size: 5,
+            ..Config::default()
+        };
+        node(id, size, config)
+    }
+
+    fn overlay(size: usize) -> Vec<Node<u64>> {
+        (0..size)
+            .map(|i| overlay_node(&format!("n{}", i), size))
+            .collect()
+    }
+
+    /// Delivers `messages` and everything sent in answer, in order.
+    fn deliver(nodes: &mut [Node<u64>], messages: Vec<Message<u64>>) {
+        let mut queue = VecDeque::from(messages);
+        while let Some(message) = queue.pop_front() {
+            let node = nodes
+                .iter_mut()
+                .find(|node| node.id == message.dest)
+                .unwrap();
+            queue.extend(match message.body {
+                RequestBody::Join { .. } => node.on_join(message),
+                RequestBody::ForwardJoin { .. } => node.on_forward_join(message),
+                RequestBody::Neighbor { .. } => node.on_neighbor(message),
+                RequestBody::NeighborOk { .. } => node.on_neighbor_ok(message),
+                RequestBody::Disconnect { .. } => node.on_disconnect(message),
+                RequestBody::Shuffle { .. } => node.on_shuffle(message),
+                RequestBody::ShuffleOk { .. } => node.on_shuffle_ok(message),
+                _ => vec![],
+            });
+        }
+    }
+
+    fn ids(ids: &[&str]) -> BTreeSet<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn joins_leave_every_node_with_symmetric_views() {
+        let mut nodes = overlay(8);
+        for i in 0..nodes.len() {
+            let joining = nodes[i].on_start();
+            deliver(&mut nodes, joining);
+        }
+        // a node can be evicted by every peer it had, until it promotes one
+        // from its passive view
+        for _ in 0..3 {
+            for i in 0..nodes.len() {
+                let repairs = nodes[i].membership_tick();
+                deliver(&mut nodes, repairs);
+            }
+        }
+
+        for node in &nodes {
+            let view = &node.hyparview;
+            assert!(!view.active.is_empty(), "{} is isolated", node.id);
+            assert!(view.active.len() <= 3);
+            assert!(view.active.is_disjoint(&view.passive));
+            for peer in &view.active {
+                let peer = nodes.iter().find(|other| other.id == *peer).unwrap();
+                assert!(
+                    peer.hyparview.active.contains(&node.id),
+                    "{} has {} active but not the other way round",
+                    node.id,
+                    peer.id
+                );
+            }
+        }
+        // the random walks leave joiners in passive views along the way
+        assert!(nodes.iter().any(|node| !node.hyparview.passive.is_empty()));
+    }
+
+    #[test]
+    fn full_active_view_makes_room_with_a_disconnect() {
+        let mut n0 = overlay_node("n0", 5);
+        for joiner in ["n1", "n2", "n3"] {
+            n0.on_join(message(joiner, "n0", RequestBody::Join { msg_id: 1 }));
+        }
+
+        let sent = n0.on_join(message("n4", "n0", RequestBody::Join { msg_id: 1 }));
+
+        assert_eq!(n0.hyparview.active.len(), 3);
+        assert!(n0.hyparview.active.contains("n4"));
+        let evicted: Vec<&String> = sent
+            .iter()
+            .filter(|sent| matches!(sent.body, RequestBody::Disconnect { .. }))
+            .map(|sent| &sent.dest)
+            .collect();
+        let [evicted] = evicted[..] else {
+            panic!("expected one disconnect, got {:?}", sent);
+        };
+        assert!(!n0.hyparview.active.contains(evicted));
+        assert!(n0.hyparview.passive.contains(evicted));
+    }
+
+    #[test]
+    fn disconnect_moves_the_sender_to_the_passive_view() {
+        let mut n0 = overlay_node("n0", 3);
+        n0.add_active("n1");
+
+        n0.on_disconnect(message("n1", "n0", RequestBody::Disconnect { msg_id: 1 }));
+
+        assert!(n0.hyparview.active.is_empty());
+        assert_eq!(n0.hyparview.passive, ids(&["n1"]));
+    }
+
+    #[test]
+    fn shuffle_exchanges_passive_views() {
+        let mut nodes = overlay(6);
+        nodes[0].add_active("n1");
+        nodes[0].add_passive("n2");
+        nodes[0].add_passive("n3");
+        nodes[1].add_active("n0");
+        nodes[1].add_passive("n4");
+        nodes[1].add_passive("n5");
+
+        // the tick also asks n2 to fill the free active slot
+        let shuffle: Vec<Message<u64>> = nodes[0]
+            .membership_tick()
+            .into_iter()
+            .filter(|sent| matches!(sent.body, RequestBody::Shuffle { .. }))
+            .collect();
+        let [Message {
+            dest,
+            body: RequestBody::Shuffle { nodes: sample, .. },
+            ..
+        }] = &shuffle[..]
+        else {
+            panic!("expected one shuffle, got {:?}", shuffle);
+        };
+        assert_eq!(dest, "n1");
+        assert!(sample.contains(&"n0".to_string()));
+        deliver(&mut nodes, shuffle);
+
+        // n1 has nowhere else to walk to, so it takes n0's sample and
+        // answers with its own passive view
+        assert!(nodes[1].hyparview.passive.is_superset(&ids(&["n2", "n3"])));
+        assert!(nodes[0].hyparview.passive.is_superset(&ids(&["n4", "n5"])));
+        for node in &nodes[..2] {
+            assert!(!node.hyparview.passive.contains(&node.id));
+            assert!(node.hyparview.active.is_disjoint(&node.hyparview.passive));
+        }
+    }
+
+    #[test]
+    fn failed_active_peer_is_replaced_from_the_passive_view() {
+        let mut n0 = overlay_node("n0", 3);
+        n0.config.failure_timeout = Duration::ZERO;
+        n0.add_active("n1");
+        n0.add_passive("n2");
+        n0.hyparview.expect_reply("n1");
+
+        let sent = n0.membership_tick();
+
+        assert!(n0.hyparview.active.is_empty());
+        assert!(!n0.hyparview.passive.contains("n1"));
+        assert!(sent.iter().any(|sent| sent.dest == "n2"
+            && matches!(
+                sent.body,
+                RequestBody::Neighbor {
+                    high_priority: true,
+                    ..
+                }
+            )));
+
+        let accepted = RequestBody::NeighborOk {
+            msg_id: 1,
+            in_reply_to: 1,
+            accepted: true,
+        };
+        n0.on_neighbor_ok(message("n2", "n0", accepted));
+        assert_eq!(n0.hyparview.active, ids(&["n2"]));
+    }
+}
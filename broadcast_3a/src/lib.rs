@@ -0,0 +1,4 @@
+//! Pieces of the broadcast node other Maelstrom nodes build on.
+
+pub mod swim;
+pub mod topology;
@@ -1,41 +1,119 @@
+mod config;
+mod failure_detector;
 mod gossip;
+mod hyparview;
+mod message_set;
 mod node;
+mod pacer;
 mod packet;
+mod plumtree;
+mod value;
 
+use config::{Config, Strategy, ValueType};
+use failure_detector::FailureDetector;
 use gossip::Gossip;
+use hyparview::Membership;
 use node::Node;
 use packet::{write_to_stdout, Message, RequestBody};
-use std::io::{stdin, BufRead};
+use plumtree::EpidemicTree;
+use std::{
+    io::{stdin, BufRead},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::Instant,
+};
+use value::{Value, ValueSet};
 
 fn main() {
-    let mut node = init_node();
+    let config = Config::from_env();
+    match config.value_type {
+        ValueType::Integer => run::<u64>(config),
+        ValueType::Json => run::<serde_json::Value>(config),
+    }
+}
 
-    for line in stdin().lock().lines() {
-        let message: Message = Message::parse_message(line.unwrap());
+fn run<V: Value>(config: Config) {
+    let mut node = init_node::<V>(config);
+    for to_send in node.on_start() {
+        write_to_stdout(to_send);
+    }
 
-        match message.body {
-            RequestBody::Topology { .. } => {
-                let to_send = node.on_topology(message).unwrap();
-                write_to_stdout(to_send);
-            }
-            RequestBody::Read { .. } => {
-                let to_send = node.on_read(message).unwrap();
-                write_to_stdout(to_send);
-            }
-            RequestBody::Broadcast { .. } => {
-                let to_send = node.on_broadcast(message).unwrap();
+    let (reader_tx, reader_rx) = mpsc::channel::<Message<V>>();
+    thread::spawn(move || read_from_stdin(reader_tx));
+
+    let tick_interval = node.config.tick_interval;
+    let mut last_tick = Instant::now();
+    loop {
+        let timeout = tick_interval.saturating_sub(last_tick.elapsed());
+
+        match reader_rx.recv_timeout(timeout) {
+            Ok(message) => handle_message(&mut node, message),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_tick.elapsed() >= tick_interval {
+            for to_send in node.on_tick() {
                 write_to_stdout(to_send);
             }
-            RequestBody::Error { .. } => {
-                let to_send = node.on_error(message).unwrap();
-                write_to_stdout(to_send);
+            last_tick = Instant::now();
+        }
+    }
+}
+
+fn read_from_stdin<V: Value>(tx: Sender<Message<V>>) {
+    for line in stdin().lock().lines() {
+        let message: Message<V> = Message::parse_message(line.unwrap());
+        tx.send(message).unwrap();
+    }
+}
+
+fn handle_message<V: Value>(node: &mut Node<V>, message: Message<V>) {
+    let mut to_send = node.heard_from(&message);
+
+    to_send.extend(match message.body {
+        RequestBody::Topology { .. } => node.on_topology(message).into_iter().collect(),
+        RequestBody::Read { .. } => node.on_read(message).into_iter().collect(),
+        RequestBody::ReadSince { .. } => node.on_read_since(message).into_iter().collect(),
+        RequestBody::Broadcast {
+            message: ref msg, ..
+        } => {
+            let mut messages = V::Set::default();
+            messages.insert(msg.clone());
+
+            let mut to_send: Vec<Message<V>> = node.on_broadcast(message).into_iter().collect();
+            if node.config.strategy == Strategy::Plumtree {
+                to_send.extend(node.tree_broadcast(messages));
             }
-            _ => panic!("unknown type"),
-        };
+            to_send
+        }
+        RequestBody::Gossip { .. } => node.on_gossip(message).into_iter().collect(),
+        RequestBody::GossipOk { .. } => node.on_gossip_ok(message).into_iter().collect(),
+        RequestBody::TreePush { .. } => node.on_tree_push(message),
+        RequestBody::IHave { .. } => node.on_ihave(message),
+        RequestBody::Graft { .. } => node.on_graft(message),
+        RequestBody::Prune { .. } => node.on_prune(message),
+        RequestBody::Join { .. } => node.on_join(message),
+        RequestBody::ForwardJoin { .. } => node.on_forward_join(message),
+        RequestBody::Neighbor { .. } => node.on_neighbor(message),
+        RequestBody::NeighborOk { .. } => node.on_neighbor_ok(message),
+        RequestBody::Disconnect { .. } => node.on_disconnect(message),
+        RequestBody::Shuffle { .. } => node.on_shuffle(message),
+        RequestBody::ShuffleOk { .. } => node.on_shuffle_ok(message),
+        RequestBody::Ping { .. } => node.on_ping(message),
+        RequestBody::PingOk { .. } => node.on_ping_ok(message),
+        RequestBody::PingReq { .. } => node.on_ping_req(message),
+        RequestBody::PingReqOk { .. } => node.on_ping_req_ok(message),
+        RequestBody::Error { .. } => node.on_error(message).into_iter().collect(),
+        _ => panic!("unknown type"),
+    });
+
+    for to_send in to_send {
+        write_to_stdout(to_send);
     }
 }
 
-fn init_node() -> Node {
+fn init_node<V: Value>(config: Config) -> Node<V> {
     let mut message = String::new();
     stdin().read_line(&mut message).unwrap();
 
@@ -43,9 +121,9 @@ fn init_node() -> Node {
     let node;
     match init_message.body {
         RequestBody::Init { msg_id, .. } => {
-            node = Node::init(init_message.clone());
+            node = Node::init(init_message.clone(), config);
 
-            let to_send = Message {
+            let to_send: Message<V> = Message {
                 src: init_message.dest,
                 dest: init_message.src,
                 body: RequestBody::InitOk {
@@ -0,0 +1,276 @@
+use serde::{Deserialize, Serialize};
+
+use crate::value::ValueSet;
+
+/// Set of broadcast values stored as sorted, disjoint, inclusive ranges.
+///
+/// Maelstrom hands out broadcast values as mostly dense integers, so a run of
+/// values collapses into a single `[start, end]` pair both in memory and on the
+/// wire. Serializes as `[[start, end], ...]`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<(u64, u64)>", into = "Vec<(u64, u64)>")]
+pub struct MessageSet {
+    ranges: Vec<(u64, u64)>,
+}
+
+impl ValueSet<u64> for MessageSet {
+    /// Inserts `value`, returning `false` if it was already present.
+    fn insert(&mut self, value: u64) -> bool {
+        // index of the first range starting after `value`
+        let idx = self.ranges.partition_point(|&(start, _)| start <= value);
+
+        if idx > 0 {
+            let (_, end) = self.ranges[idx - 1];
+            if value <= end {
+                return false;
+            }
+        }
+
+        let joins_prev = idx > 0 && self.ranges[idx - 1].1 + 1 == value;
+        let joins_next =
+            idx < self.ranges.len() && value.checked_add(1) == Some(self.ranges[idx].0);
+
+        match (joins_prev, joins_next) {
+            (true, true) => {
+                self.ranges[idx - 1].1 = self.ranges[idx].1;
+                self.ranges.remove(idx);
+            }
+            (true, false) => self.ranges[idx - 1].1 = value,
+            (false, true) => self.ranges[idx].0 = value,
+            (false, false) => self.ranges.insert(idx, (value, value)),
+        }
+
+        true
+    }
+
+    fn contains(&self, value: &u64) -> bool {
+        let value = *value;
+        let idx = self.ranges.partition_point(|&(start, _)| start <= value);
+        idx > 0 && value <= self.ranges[idx - 1].1
+    }
+
+    /// Adds every value of `other` to this set.
+    fn union(&mut self, other: &MessageSet) {
+        if other.ranges.is_empty() {
+            return;
+        }
+
+        let mut ranges = Vec::with_capacity(self.ranges.len() + other.ranges.len());
+        ranges.extend_from_slice(&self.ranges);
+        ranges.extend_from_slice(&other.ranges);
+
+        self.ranges = normalize(ranges);
+    }
+
+    /// Returns the values of this set that are not in `other`.
+    fn difference(&self, other: &MessageSet) -> MessageSet {
+        let mut ranges = Vec::new();
+        let mut theirs = other.ranges.iter().peekable();
+
+        for &(start, end) in &self.ranges {
+            // first value of the current range not yet known to be covered
+            let mut cursor = Some(start);
+
+            while let (Some(from), Some(&&(other_start, other_end))) = (cursor, theirs.peek()) {
+                if other_end < from {
+                    theirs.next();
+                    continue;
+                }
+                if other_start > end {
+                    break;
+                }
+                if other_start > from {
+                    ranges.push((from, other_start - 1));
+                }
+                if other_end >= end {
+                    cursor = None;
+                    break;
+                }
+                cursor = Some(other_end + 1);
+                theirs.next();
+            }
+
+            if let Some(from) = cursor {
+                ranges.push((from, end));
+            }
+        }
+
+        MessageSet { ranges }
+    }
+
+    /// Returns the values present in both sets.
+    fn intersection(&self, other: &MessageSet) -> MessageSet {
+        self.difference(&self.difference(other))
+    }
+
+    /// Saturates at `u64::MAX`: the full range holds one value more.
+    fn len(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|&(start, end)| (end - start).saturating_add(1))
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Returns the `limit` smallest values of this set.
+    fn truncated(&self, limit: u64) -> MessageSet {
+        let mut ranges = Vec::new();
+        let mut remaining = limit;
+
+        for &(start, end) in &self.ranges {
+            if remaining == 0 {
+                break;
+            }
+            let take = (end - start).min(remaining - 1);
+            ranges.push((start, start + take));
+            remaining -= take + 1;
+        }
+
+        MessageSet { ranges }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    fn values(&self) -> Vec<u64> {
+        self.ranges
+            .iter()
+            .flat_map(|&(start, end)| start..=end)
+            .collect()
+    }
+}
+
+impl From<Vec<(u64, u64)>> for MessageSet {
+    fn from(ranges: Vec<(u64, u64)>) -> Self {
+        MessageSet {
+            ranges: normalize(ranges),
+        }
+    }
+}
+
+impl From<MessageSet> for Vec<(u64, u64)> {
+    fn from(set: MessageSet) -> Self {
+        set.ranges
+    }
+}
+
+/// Sorts ranges and merges the ones that overlap or touch. Ranges coming off
+/// the wire are not trusted to be well formed, so inverted ones are dropped.
+fn normalize(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.retain(|&(start, end)| start <= end);
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(set: &MessageSet) -> Vec<(u64, u64)> {
+        set.clone().into()
+    }
+
+    #[test]
+    fn insert_joins_adjacent_ranges() {
+        let mut set = MessageSet::from(vec![(1, 2), (4, 5)]);
+
+        assert!(set.insert(7));
+        assert_eq!(ranges(&set), [(1, 2), (4, 5), (7, 7)]);
+        assert!(set.insert(6));
+        assert_eq!(ranges(&set), [(1, 2), (4, 7)]);
+        assert!(set.insert(3));
+        assert_eq!(ranges(&set), [(1, 7)]);
+        assert!(set.insert(0));
+        assert_eq!(ranges(&set), [(0, 7)]);
+    }
+
+    #[test]
+    fn insert_of_a_present_value_changes_nothing() {
+        let mut set = MessageSet::from(vec![(1, 3)]);
+
+        for value in 1..=3 {
+            assert!(!set.insert(value));
+        }
+        assert_eq!(ranges(&set), [(1, 3)]);
+    }
+
+    #[test]
+    fn insert_at_the_top_of_the_range_of_values() {
+        let mut set = MessageSet::default();
+
+        assert!(set.insert(u64::MAX));
+        assert!(set.insert(u64::MAX - 1));
+        assert_eq!(ranges(&set), [(u64::MAX - 1, u64::MAX)]);
+        assert!(set.contains(&u64::MAX));
+    }
+
+    #[test]
+    fn union_merges_overlapping_and_adjacent_ranges() {
+        let mut set = MessageSet::from(vec![(1, 4), (10, 12)]);
+
+        set.union(&MessageSet::from(vec![(3, 6), (7, 7), (13, 20)]));
+
+        assert_eq!(ranges(&set), [(1, 7), (10, 20)]);
+    }
+
+    #[test]
+    fn difference_cuts_ranges_at_their_boundaries() {
+        let set = MessageSet::from(vec![(1, 10), (20, 30)]);
+        let other = MessageSet::from(vec![(1, 1), (5, 6), (10, 21), (30, 40)]);
+
+        assert_eq!(ranges(&set.difference(&other)), [(2, 4), (7, 9), (22, 29)]);
+        assert_eq!(ranges(&other.difference(&set)), [(11, 19), (31, 40)]);
+        assert!(set.difference(&set).is_empty());
+        assert_eq!(set.difference(&MessageSet::default()), set);
+    }
+
+    #[test]
+    fn intersection_keeps_values_in_both() {
+        let set = MessageSet::from(vec![(1, 10), (20, 30)]);
+        let other = MessageSet::from(vec![(5, 25), (30, 31)]);
+
+        assert_eq!(
+            ranges(&set.intersection(&other)),
+            [(5, 10), (20, 25), (30, 30)]
+        );
+        assert!(set.intersection(&MessageSet::default()).is_empty());
+    }
+
+    #[test]
+    fn ranges_off_the_wire_are_normalized() {
+        let set: MessageSet = serde_json::from_str("[[8,9],[5,3],[1,2],[2,4],[6,6]]").unwrap();
+
+        // the inverted [5,3] is dropped, the rest sorted and merged
+        assert_eq!(ranges(&set), [(1, 4), (6, 6), (8, 9)]);
+        assert_eq!(serde_json::to_string(&set).unwrap(), "[[1,4],[6,6],[8,9]]");
+    }
+
+    #[test]
+    fn truncation_splits_a_range_at_the_limit() {
+        let set = MessageSet::from(vec![(1, 3), (10, 20)]);
+
+        assert!(set.truncated(0).is_empty());
+        assert_eq!(ranges(&set.truncated(2)), [(1, 2)]);
+        assert_eq!(ranges(&set.truncated(3)), [(1, 3)]);
+        assert_eq!(ranges(&set.truncated(4)), [(1, 3), (10, 10)]);
+        assert_eq!(set.truncated(100), set);
+        assert_eq!(set.truncated(5).len(), 5);
+    }
+
+    #[test]
+    fn len_saturates_for_the_full_range() {
+        let set = MessageSet::from(vec![(0, u64::MAX)]);
+
+        assert_eq!(set.len(), u64::MAX);
+        assert_eq!(ranges(&set.truncated(u64::MAX)), [(0, u64::MAX - 1)]);
+    }
+}
@@ -1,53 +1,370 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
 
+use broadcast_3a::{
+    swim::{Health, Swim},
+    topology::Topology,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::packet::{Message, RequestBody};
+use crate::{
+    config::{Config, Membership, Strategy},
+    failure_detector::FailureDetector,
+    gossip::Gossip,
+    hyparview::{HyParView, Membership as _},
+    pacer::Pacer,
+    packet::{Message, RequestBody},
+    plumtree::{EpidemicTree, Plumtree},
+    value::{Value, ValueSet},
+};
 
-#[derive(Debug, Default)]
-pub struct Node {
+#[derive(Debug)]
+pub struct Node<V: Value> {
     pub id: String,
     pub peers: Vec<String>,
-    pub storage: Storage,
+    pub storage: Storage<V>,
+    pub(crate) next_msg_id: u64,
+    pub(crate) config: Config,
+    pub(crate) plumtree: Plumtree<V>,
+    pub(crate) hyparview: HyParView,
+    pub(crate) swim: Swim,
+    pub(crate) pacer: Pacer,
+    last_gossip: Option<Instant>,
 }
 
-impl Node {
-    pub fn init(message: Message) -> Self {
+impl<V: Value> Node<V> {
+    pub fn init(message: Message<V>, config: Config) -> Self {
         match message.body {
             RequestBody::Init {
                 node_id, node_ids, ..
             } => {
-                return Node {
+                let swim = Swim::new(&node_id, &node_ids);
+
+                Node {
                     id: node_id,
                     peers: node_ids,
                     storage: Storage::new(),
+                    next_msg_id: 0,
+                    config,
+                    plumtree: Plumtree::default(),
+                    hyparview: HyParView::default(),
+                    swim,
+                    pacer: Pacer::default(),
+                    last_gossip: None,
                 }
             }
             _ => panic!("Invalid message type"),
         }
     }
+
+    pub(crate) fn msg_id(&mut self) -> u64 {
+        self.next_msg_id += 1;
+        self.next_msg_id
+    }
+
+    /// Messages to send once the node has answered `init`.
+    pub(crate) fn on_start(&mut self) -> Vec<Message<V>> {
+        match self.config.membership {
+            Membership::Static => vec![],
+            Membership::HyParView => self.join(),
+        }
+    }
+
+    /// Called for every message received from another node.
+    pub(crate) fn heard_from(&mut self, message: &Message<V>) -> Vec<Message<V>> {
+        self.hyparview.heard_from(&message.src);
+
+        // gossip from outside the active view means the sender still counts
+        // us as a neighbour, so tell it otherwise and let it pick someone else
+        let stale_link = self.config.membership == Membership::HyParView
+            && matches!(message.body, RequestBody::Gossip { .. })
+            && !self.hyparview.active.contains(&message.src);
+        if !stale_link {
+            return vec![];
+        }
+
+        vec![Message {
+            src: self.id.clone(),
+            dest: message.src.clone(),
+            body: RequestBody::Disconnect {
+                msg_id: self.msg_id(),
+            },
+        }]
+    }
+
+    /// Runs whatever timers are due and returns the messages they produce.
+    pub(crate) fn on_tick(&mut self) -> Vec<Message<V>> {
+        let mut to_send = Vec::new();
+
+        if self.config.swim {
+            to_send.extend(self.swim_tick());
+        }
+
+        if self.config.membership == Membership::HyParView {
+            to_send.extend(self.membership_tick());
+        }
+
+        if self.config.strategy == Strategy::Plumtree {
+            to_send.extend(self.tree_tick());
+        }
+
+        self.pacer.sample();
+        self.storage
+            .in_flight
+            .retain(|msg_id, _| self.pacer.awaits_ack(*msg_id));
+        let interval = self.gossip_interval();
+        let gossip_due = self.last_gossip.is_none_or(|at| at.elapsed() >= interval);
+        if gossip_due {
+            let gossip = self.gossip();
+            for message in &gossip {
+                self.hyparview.expect_reply(&message.dest);
+                if let RequestBody::Gossip { msg_id, .. } = message.body {
+                    self.pacer.record_gossip(msg_id);
+                }
+            }
+            to_send.extend(gossip);
+            self.last_gossip = Some(Instant::now());
+        }
+
+        to_send
+    }
+
+    pub(crate) fn gossip_interval(&self) -> Duration {
+        if !self.config.adaptive {
+            return self.config.gossip_interval;
+        }
+        self.pacer
+            .interval(&self.config, self.peers.len(), self.neighbours().len())
+    }
+
+    /// Most values a single gossip message may carry.
+    pub(crate) fn gossip_batch_size(&self) -> u64 {
+        if !self.config.adaptive {
+            return u64::MAX;
+        }
+        self.pacer.batch_size(&self.config, self.gossip_interval())
+    }
+
+    /// Health of `peer` according to the failure detector. Without SWIM
+    /// every peer is assumed alive.
+    pub(crate) fn health(&self, peer: &str) -> Health {
+        if !self.config.swim {
+            return Health::Alive;
+        }
+        self.swim.health(peer)
+    }
+
+    /// Nodes this node gossips with, leaving out the ones known to be dead.
+    /// With static membership that is its entry in the topology once one has
+    /// been received, every other node in the cluster until then.
+    pub(crate) fn neighbours(&self) -> Vec<String> {
+        let neighbours: Vec<String> = if self.config.membership == Membership::HyParView {
+            self.hyparview.active.iter().cloned().collect()
+        } else {
+            self.storage.topology.neighbours(&self.id, &self.peers)
+        };
+
+        neighbours
+            .into_iter()
+            .filter(|peer| self.health(peer) != Health::Dead)
+            .collect()
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default)]
-pub struct Storage {
-    pub(crate) messages: Vec<u64>,
-    pub(crate) topology: HashMap<String, Vec<String>>,
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(bound = "V: Value")]
+pub struct Storage<V: Value> {
+    pub(crate) messages: V::Set,
+    /// Every value in `messages`, in batches in the order this node first
+    /// saw them. Batch indexes are the cursors handed out by `read_since`,
+    /// so values stay in their compact set form until a read asks for them.
+    pub(crate) log: Vec<V::Set>,
+    /// Batches before this one are behind a cursor already handed out and
+    /// must not grow; newer values go into the last batch otherwise.
+    sealed: usize,
+    pub(crate) topology: Topology,
+    /// Values each peer is known to hold, either because it sent them to us
+    /// or acknowledged them. These are never gossiped to that peer again.
+    pub(crate) known: HashMap<String, V::Set>,
+    /// Values carried by each gossip still waiting for its `gossip_ok`, by
+    /// msg_id. Given up on along with the pacer's round-trip sample.
+    pub(crate) in_flight: HashMap<u64, V::Set>,
 }
 
-impl Storage {
-    pub(crate) fn new() -> Storage {
-        Storage::default()
+impl<V: Value> Storage<V> {
+    pub(crate) fn new() -> Storage<V> {
+        Storage {
+            messages: V::Set::default(),
+            log: Vec::new(),
+            sealed: 0,
+            topology: Topology::default(),
+            known: HashMap::new(),
+            in_flight: HashMap::new(),
+        }
     }
 
     pub(crate) fn init_topology(&mut self, topology: HashMap<String, Vec<String>>) {
-        self.topology = topology;
+        self.topology.init(topology);
+    }
+
+    pub(crate) fn get_messages(&mut self) -> Vec<V> {
+        self.messages.values()
+    }
+
+    /// Values added after `cursor`, and the cursor to continue from. A
+    /// cursor this node never handed out, e.g. one from another node, reads
+    /// from the start.
+    pub(crate) fn get_messages_since(&mut self, cursor: Option<u64>) -> (Vec<V>, u64) {
+        let end = self.log.len();
+        let start = match cursor {
+            Some(cursor) if cursor <= end as u64 => cursor as usize,
+            _ => 0,
+        };
+        self.sealed = end;
+
+        let messages = self.log[start..]
+            .iter()
+            .flat_map(|batch| batch.values())
+            .collect();
+        (messages, end as u64)
+    }
+
+    pub(crate) fn add_message(&mut self, message: V) -> bool {
+        if !self.messages.insert(message.clone()) {
+            return false;
+        }
+        self.open_batch().insert(message);
+        true
+    }
+
+    pub(crate) fn add_messages(&mut self, messages: &V::Set) {
+        let fresh = messages.difference(&self.messages);
+        if fresh.is_empty() {
+            return;
+        }
+        self.open_batch().union(&fresh);
+        self.messages.union(&fresh);
+    }
+
+    /// The batch new values go into, started afresh once a cursor covers
+    /// the last one.
+    fn open_batch(&mut self) -> &mut V::Set {
+        if self.log.len() <= self.sealed {
+            self.log.push(V::Set::default());
+        }
+        self.log.last_mut().expect("the log has an open batch")
+    }
+
+    pub(crate) fn mark_known(&mut self, peer: &str, messages: &V::Set) {
+        self.known
+            .entry(peer.to_string())
+            .or_default()
+            .union(messages);
+    }
+
+    /// Values `peer` is not known to hold yet.
+    pub(crate) fn unknown_to(&self, peer: &str) -> V::Set {
+        match self.known.get(peer) {
+            Some(known) => self.messages.difference(known),
+            None => self.messages.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::message_set::MessageSet;
+
+    /// Node `id` of a cluster of `size` nodes named n0, n1, ..., as
+    /// Maelstrom starts it.
+    pub(crate) fn node(id: &str, size: usize, config: Config) -> Node<u64> {
+        let init = RequestBody::Init {
+            msg_id: 0,
+            node_id: id.to_string(),
+            node_ids: (0..size).map(|i| format!("n{}", i)).collect(),
+        };
+        Node::init(message("c0", id, init), config)
     }
 
-    pub(crate) fn get_messages(&mut self) -> Vec<u64> {
-        self.messages.to_owned()
+    pub(crate) fn set(values: &[u64]) -> MessageSet {
+        let mut set = MessageSet::default();
+        for value in values {
+            set.insert(*value);
+        }
+        set
+    }
+
+    /// A message from `src` to `dest`.
+    pub(crate) fn message(src: &str, dest: &str, body: RequestBody<u64>) -> Message<u64> {
+        Message {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body,
+        }
+    }
+
+    fn sorted(mut values: Vec<u64>) -> Vec<u64> {
+        values.sort_unstable();
+        values
+    }
+
+    #[test]
+    fn read_without_a_cursor_returns_everything() {
+        let mut storage = Storage::<u64>::new();
+        storage.add_message(1);
+        storage.add_messages(&set(&[2, 3]));
+
+        let (messages, _) = storage.get_messages_since(None);
+
+        assert_eq!(sorted(messages), [1, 2, 3]);
     }
 
-    pub(crate) fn add_message(&mut self, message: u64) {
-        self.messages.push(message);
+    #[test]
+    fn read_from_a_cursor_returns_only_newer_values() {
+        let mut storage = Storage::<u64>::new();
+        storage.add_message(1);
+        let (_, cursor) = storage.get_messages_since(None);
+
+        storage.add_message(2);
+        storage.add_messages(&set(&[1, 3]));
+        let (messages, next) = storage.get_messages_since(Some(cursor));
+        assert_eq!(sorted(messages), [2, 3]);
+
+        let (messages, _) = storage.get_messages_since(Some(next));
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn batch_behind_a_cursor_is_not_read_again() {
+        let mut storage = Storage::<u64>::new();
+        storage.add_message(1);
+        let (_, first) = storage.get_messages_since(None);
+        storage.add_message(2);
+        let (_, second) = storage.get_messages_since(Some(first));
+
+        // 3 has to go into a batch of its own, not the one `second` covers
+        storage.add_message(3);
+
+        let (messages, _) = storage.get_messages_since(Some(second));
+        assert_eq!(messages, [3]);
+        let (messages, _) = storage.get_messages_since(Some(first));
+        assert_eq!(sorted(messages), [2, 3]);
+    }
+
+    #[test]
+    fn cursor_this_node_never_handed_out_reads_from_the_start() {
+        let mut storage = Storage::<u64>::new();
+        storage.add_message(1);
+        storage.add_message(2);
+        let (_, cursor) = storage.get_messages_since(None);
+
+        // e.g. one handed out by a node further along
+        let (messages, next) = storage.get_messages_since(Some(cursor + 10));
+
+        assert_eq!(sorted(messages), [1, 2]);
+        assert_eq!(next, cursor);
     }
 }
@@ -0,0 +1,225 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::config::Config;
+
+/// Weight of the newest sample in the moving averages.
+const SMOOTHING: f64 = 0.2;
+/// Gossip that has not been acknowledged by then is not waited for.
+const ACK_HORIZON: Duration = Duration::from_secs(5);
+
+/// Picks the anti-entropy gossip interval and batch size from what the node
+/// observes: how fast clients hand it new values and how long gossip takes
+/// to be acknowledged.
+///
+/// Each gossip round costs a `gossip` and a `gossip_ok` per neighbour, so the
+/// interval is stretched until that cost, spread over the broadcasts seen in
+/// the meantime, meets the msgs-per-op target. It is capped so that a value
+/// still crosses the expected number of hops within the latency target.
+#[derive(Debug, Default)]
+pub struct Pacer {
+    /// Client broadcasts per second.
+    broadcast_rate: f64,
+    broadcasts_since_sample: u64,
+    /// Values new to this node per second, from clients and gossip alike.
+    value_rate: f64,
+    values_since_sample: u64,
+    last_sample: Option<Instant>,
+    /// Gossip round-trip time, in seconds.
+    rtt: Option<f64>,
+    in_flight: HashMap<u64, Instant>,
+}
+
+impl Pacer {
+    pub(crate) fn record_broadcast(&mut self) {
+        self.broadcasts_since_sample += 1;
+    }
+
+    pub(crate) fn record_new_values(&mut self, count: u64) {
+        self.values_since_sample = self.values_since_sample.saturating_add(count);
+    }
+
+    pub(crate) fn record_gossip(&mut self, msg_id: u64) {
+        self.in_flight.insert(msg_id, Instant::now());
+    }
+
+    pub(crate) fn record_ack(&mut self, in_reply_to: u64) {
+        if let Some(sent) = self.in_flight.remove(&in_reply_to) {
+            let sample = sent.elapsed().as_secs_f64();
+            self.rtt = Some(match self.rtt {
+                Some(rtt) => rtt + SMOOTHING * (sample - rtt),
+                None => sample,
+            });
+        }
+    }
+
+    /// Whether gossip `msg_id` is still waiting for its acknowledgement.
+    pub(crate) fn awaits_ack(&self, msg_id: u64) -> bool {
+        self.in_flight.contains_key(&msg_id)
+    }
+
+    /// Folds the broadcasts counted since the last call into the rate.
+    pub(crate) fn sample(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_sample {
+            let elapsed = now.duration_since(last).as_secs_f64();
+            if elapsed > 0.0 {
+                let rate = self.broadcasts_since_sample as f64 / elapsed;
+                self.broadcast_rate += SMOOTHING * (rate - self.broadcast_rate);
+                let rate = self.values_since_sample as f64 / elapsed;
+                self.value_rate += SMOOTHING * (rate - self.value_rate);
+            }
+        }
+
+        self.broadcasts_since_sample = 0;
+        self.values_since_sample = 0;
+        self.last_sample = Some(now);
+        self.in_flight
+            .retain(|_, sent| now.duration_since(*sent) < ACK_HORIZON);
+    }
+
+    pub(crate) fn interval(
+        &self,
+        config: &Config,
+        cluster_size: usize,
+        neighbours: usize,
+    ) -> Duration {
+        let neighbours = neighbours.max(1) as f64;
+
+        // slowest interval that still meets the latency target
+        let hops = ((cluster_size.max(2) as f64).ln() / neighbours.max(2.0).ln()).ceil();
+        let per_hop = config.target_latency.as_secs_f64() / hops.max(1.0);
+        let latency_bound = (per_hop - self.rtt.unwrap_or(0.0)).max(0.0);
+
+        // fastest interval that still meets the msgs-per-op target
+        let messages_bound = if self.broadcast_rate > 0.0 {
+            2.0 * neighbours / (config.target_msgs_per_op * self.broadcast_rate)
+        } else {
+            latency_bound
+        };
+
+        let interval = messages_bound.min(latency_bound);
+        Duration::from_secs_f64(interval)
+            .clamp(config.min_gossip_interval, config.max_gossip_interval)
+    }
+
+    /// How many values a single gossip message may carry: enough for what
+    /// arrives in a couple of intervals, so a backlog drains without sending
+    /// one huge message after a partition heals.
+    pub(crate) fn batch_size(&self, config: &Config, interval: Duration) -> u64 {
+        let expected = self.value_rate * interval.as_secs_f64();
+        ((expected * 2.0).ceil() as u64).max(config.min_batch_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 25 nodes with 4 neighbours each are 3 hops apart, so with the default
+    // 500ms target each hop gets 1/6 s
+    const PER_HOP: f64 = 0.5 / 3.0;
+
+    fn pacer(broadcast_rate: f64, rtt: Option<f64>) -> Pacer {
+        Pacer {
+            broadcast_rate,
+            rtt,
+            ..Pacer::default()
+        }
+    }
+
+    fn assert_close(actual: Duration, expected: f64) {
+        let error = (actual.as_secs_f64() - expected).abs();
+        assert!(error < 1e-6, "expected {}s, got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn low_rate_is_bound_by_latency() {
+        let config = Config::default();
+
+        // 2 * 4 / (20 * 1) = 0.4s would meet the message target
+        let interval = pacer(1.0, None).interval(&config, 25, 4);
+
+        assert_close(interval, PER_HOP);
+    }
+
+    #[test]
+    fn rtt_comes_out_of_the_latency_budget() {
+        let config = Config::default();
+
+        let interval = pacer(1.0, Some(0.1)).interval(&config, 25, 4);
+
+        assert_close(interval, PER_HOP - 0.1);
+    }
+
+    #[test]
+    fn higher_rate_is_bound_by_messages_per_op() {
+        let config = Config::default();
+
+        let interval = pacer(10.0, None).interval(&config, 25, 4);
+
+        assert_close(interval, 2.0 * 4.0 / (20.0 * 10.0));
+    }
+
+    #[test]
+    fn interval_is_clamped_to_the_limits() {
+        let config = Config::default();
+
+        // 2 * 4 / (20 * 100) = 4ms
+        let fast = pacer(100.0, None).interval(&config, 25, 4);
+        assert_eq!(fast, config.min_gossip_interval);
+        // an rtt longer than a hop leaves no time at all
+        let slow_acks = pacer(1.0, Some(1.0)).interval(&config, 25, 4);
+        assert_eq!(slow_acks, config.min_gossip_interval);
+
+        let relaxed = Config {
+            target_latency: Duration::from_secs(5),
+            ..Config::default()
+        };
+        let idle = pacer(0.0, None).interval(&relaxed, 2, 1);
+        assert_eq!(idle, relaxed.max_gossip_interval);
+    }
+
+    #[test]
+    fn batch_holds_two_intervals_of_new_values() {
+        let config = Config::default();
+        let pacer = Pacer {
+            value_rate: 1000.0,
+            ..Pacer::default()
+        };
+
+        assert_eq!(pacer.batch_size(&config, Duration::from_millis(100)), 200);
+        assert_eq!(pacer.batch_size(&config, Duration::from_secs(1)), 2000);
+    }
+
+    #[test]
+    fn batch_is_never_below_the_minimum() {
+        let config = Config::default();
+        let quiet = Pacer {
+            value_rate: 10.0,
+            ..Pacer::default()
+        };
+        let busy = Pacer {
+            value_rate: 1000.0,
+            ..Pacer::default()
+        };
+
+        assert_eq!(quiet.batch_size(&config, Duration::from_millis(100)), 100);
+        // a short interval, e.g. forced by high rtts, still sends a full batch
+        assert_eq!(busy.batch_size(&config, config.min_gossip_interval), 100);
+    }
+
+    #[test]
+    fn ack_of_a_gossip_records_a_round_trip() {
+        let mut pacer = Pacer::default();
+        pacer.record_gossip(1);
+        assert!(pacer.awaits_ack(1));
+
+        pacer.record_ack(1);
+
+        assert!(!pacer.awaits_ack(1));
+        assert!(pacer.rtt.is_some());
+    }
+}
@@ -1,19 +1,24 @@
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, io::Write};
 
+use broadcast_3a::swim::MemberUpdate;
+
+use crate::value::Value;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Message {
+#[serde(bound = "V: Value")]
+pub struct Message<V: Value> {
     pub src: String,
     pub dest: String,
-    pub body: RequestBody,
+    pub body: RequestBody<V>,
 }
 
-impl Message {
-    pub(crate) fn parse_message(message: String) -> Message {
+impl<V: Value> Message<V> {
+    pub(crate) fn parse_message(message: String) -> Message<V> {
         serde_json::from_str(&message).unwrap()
     }
 
-    pub(crate) fn format_message(message: Message) -> String {
+    pub(crate) fn format_message(message: Message<V>) -> String {
         serde_json::to_string(&message).unwrap()
     }
 }
@@ -21,7 +26,8 @@ impl Message {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
-pub enum RequestBody {
+#[serde(bound = "V: Value")]
+pub enum RequestBody<V: Value> {
     Init {
         msg_id: u64,
         node_id: String,
@@ -44,16 +50,109 @@ pub enum RequestBody {
     ReadOk {
         msg_id: u64,
         in_reply_to: u64,
-        messages: Vec<u64>,
+        messages: Vec<V>,
+    },
+    /// Like `read`, but only returns values added since `cursor`. Cursors are
+    /// opaque and only meaningful to the node that issued them.
+    ReadSince {
+        msg_id: u64,
+        #[serde(default)]
+        cursor: Option<u64>,
+    },
+    ReadSinceOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        messages: Vec<V>,
+        cursor: u64,
     },
     Broadcast {
         msg_id: u64,
-        message: u64,
+        message: V,
     },
     BroadcastOk {
         msg_id: u64,
         in_reply_to: u64,
     },
+    Gossip {
+        msg_id: u64,
+        messages: V::Set,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        updates: Vec<MemberUpdate>,
+    },
+    /// Acknowledges the values of the gossip it answers, which the sender
+    /// looks up by `in_reply_to` rather than having them echoed back.
+    GossipOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        updates: Vec<MemberUpdate>,
+    },
+    TreePush {
+        msg_id: u64,
+        messages: V::Set,
+    },
+    #[serde(rename = "ihave")]
+    IHave {
+        msg_id: u64,
+        messages: V::Set,
+    },
+    Graft {
+        msg_id: u64,
+        messages: V::Set,
+    },
+    Prune {
+        msg_id: u64,
+    },
+    Join {
+        msg_id: u64,
+    },
+    ForwardJoin {
+        msg_id: u64,
+        node: String,
+        ttl: u64,
+    },
+    Neighbor {
+        msg_id: u64,
+        high_priority: bool,
+    },
+    NeighborOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        accepted: bool,
+    },
+    Disconnect {
+        msg_id: u64,
+    },
+    Shuffle {
+        msg_id: u64,
+        origin: String,
+        nodes: Vec<String>,
+        ttl: u64,
+    },
+    ShuffleOk {
+        msg_id: u64,
+        nodes: Vec<String>,
+    },
+    Ping {
+        msg_id: u64,
+        updates: Vec<MemberUpdate>,
+    },
+    PingOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        updates: Vec<MemberUpdate>,
+    },
+    PingReq {
+        msg_id: u64,
+        target: String,
+        updates: Vec<MemberUpdate>,
+    },
+    PingReqOk {
+        msg_id: u64,
+        in_reply_to: u64,
+        target: String,
+        updates: Vec<MemberUpdate>,
+    },
     Error {
         in_reply_to: u64,
         code: u64,
@@ -61,7 +160,7 @@ pub enum RequestBody {
     },
 }
 
-pub fn write_to_stdout(message: Message) {
+pub fn write_to_stdout<V: Value>(message: Message<V>) {
     let mut stdout = std::io::stdout();
     let output = Message::format_message(message);
     writeln!(stdout, "{}", output).unwrap();
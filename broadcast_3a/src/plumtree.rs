@@ -0,0 +1,410 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    time::Instant,
+};
+
+use broadcast_3a::swim::Health;
+
+use crate::{
+    node::Node,
+    packet::{Message, RequestBody},
+    value::{Value, ValueSet},
+};
+
+/// Epidemic broadcast tree state (Leitão et al., "Epidemic Broadcast Trees").
+///
+/// Every neighbour starts as an eager peer. When a value arrives twice the
+/// redundant link is pruned to lazy, which leaves a spanning tree of eager
+/// links. Lazy peers only get `ihave` announcements; if an announced value
+/// does not show up through the tree in time the announcer is grafted back.
+#[derive(Debug)]
+pub struct Plumtree<V: Value> {
+    pub(crate) eager: BTreeSet<String>,
+    pub(crate) lazy: BTreeSet<String>,
+    /// Values announced to us that have not arrived yet.
+    pub(crate) missing: HashMap<V, Missing>,
+    /// Announcements waiting for the next tick, per lazy peer.
+    pub(crate) announcements: HashMap<String, V::Set>,
+    initialized: bool,
+}
+
+#[derive(Debug)]
+pub struct Missing {
+    /// Peers that announced the value, in the order they did.
+    announcers: VecDeque<String>,
+    deadline: Instant,
+}
+
+impl<V: Value> Default for Plumtree<V> {
+    fn default() -> Self {
+        Plumtree {
+            eager: BTreeSet::new(),
+            lazy: BTreeSet::new(),
+            missing: HashMap::new(),
+            announcements: HashMap::new(),
+            initialized: false,
+        }
+    }
+}
+
+impl<V: Value> Plumtree<V> {
+    fn ensure_peers(&mut self, neighbours: Vec<String>) {
+        if !self.initialized {
+            self.eager = neighbours.into_iter().collect();
+            self.initialized = true;
+        }
+    }
+
+    /// A new overlay neighbour starts out eager, as if it were there from
+    /// the beginning.
+    pub(crate) fn neighbour_up(&mut self, peer: &str) {
+        if self.initialized {
+            self.make_eager(peer);
+        }
+    }
+
+    pub(crate) fn neighbour_down(&mut self, peer: &str) {
+        self.eager.remove(peer);
+        self.lazy.remove(peer);
+        self.announcements.remove(peer);
+        for missing in self.missing.values_mut() {
+            missing.announcers.retain(|announcer| announcer != peer);
+        }
+    }
+
+    fn make_eager(&mut self, peer: &str) {
+        self.lazy.remove(peer);
+        self.eager.insert(peer.to_string());
+    }
+
+    fn make_lazy(&mut self, peer: &str) {
+        self.eager.remove(peer);
+        self.lazy.insert(peer.to_string());
+    }
+
+    fn announce(&mut self, messages: &V::Set, except: Option<&str>) {
+        for peer in &self.lazy {
+            if Some(peer.as_str()) == except {
+                continue;
+            }
+            self.announcements
+                .entry(peer.clone())
+                .or_default()
+                .union(messages);
+        }
+    }
+}
+
+pub trait EpidemicTree<V: Value> {
+    fn tree_broadcast(&mut self, messages: V::Set) -> Vec<Message<V>>;
+    fn on_tree_push(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_ihave(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_graft(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn on_prune(&mut self, message: Message<V>) -> Vec<Message<V>>;
+    fn tree_tick(&mut self) -> Vec<Message<V>>;
+}
+
+impl<V: Value> Node<V> {
+    fn eager_push(&mut self, messages: &V::Set, except: Option<&str>) -> Vec<Message<V>> {
+        let neighbours = self.neighbours();
+        self.plumtree.ensure_peers(neighbours);
+
+        let eager: Vec<String> = self
+            .plumtree
+            .eager
+            .iter()
+            .filter(|peer| Some(peer.as_str()) != except)
+            .filter(|peer| self.health(peer) != Health::Dead)
+            .cloned()
+            .collect();
+
+        self.plumtree.announce(messages, except);
+
+        eager
+            .into_iter()
+            .map(|peer| Message {
+                src: self.id.clone(),
+                dest: peer,
+                body: RequestBody::TreePush {
+                    msg_id: self.msg_id(),
+                    messages: messages.clone(),
+                },
+            })
+            .collect()
+    }
+}
+
+impl<V: Value> EpidemicTree<V> for Node<V> {
+    /// Starts disseminating values this node accepted from a client.
+    fn tree_broadcast(&mut self, messages: V::Set) -> Vec<Message<V>> {
+        self.eager_push(&messages, None)
+    }
+
+    fn on_tree_push(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::TreePush { messages, .. } => {
+                let neighbours = self.neighbours();
+                self.plumtree.ensure_peers(neighbours);
+                self.storage.mark_known(&message.src, &messages);
+
+                let fresh = messages.difference(&self.storage.messages);
+                if fresh.is_empty() {
+                    // already delivered through another path, so this link is
+                    // redundant in the tree
+                    self.plumtree.make_lazy(&message.src);
+
+                    return vec![Message {
+                        src: self.id.clone(),
+                        dest: message.src,
+                        body: RequestBody::Prune {
+                            msg_id: self.msg_id(),
+                        },
+                    }];
+                }
+
+                self.storage.add_messages(&fresh);
+                for value in fresh.values() {
+                    self.plumtree.missing.remove(&value);
+                }
+                self.plumtree.make_eager(&message.src);
+
+                self.eager_push(&fresh, Some(&message.src))
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_ihave(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::IHave { messages, .. } => {
+                self.storage.mark_known(&message.src, &messages);
+
+                let deadline = Instant::now() + self.config.graft_timeout;
+                for value in messages.difference(&self.storage.messages).values() {
+                    let missing = self.plumtree.missing.entry(value).or_insert(Missing {
+                        announcers: VecDeque::new(),
+                        deadline,
+                    });
+                    missing.announcers.push_back(message.src.clone());
+                }
+
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_graft(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::Graft { messages, .. } => {
+                self.plumtree.make_eager(&message.src);
+
+                let available = messages.intersection(&self.storage.messages);
+                if available.is_empty() {
+                    return vec![];
+                }
+
+                vec![Message {
+                    src: self.id.clone(),
+                    dest: message.src,
+                    body: RequestBody::TreePush {
+                        msg_id: self.msg_id(),
+                        messages: available,
+                    },
+                }]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn on_prune(&mut self, message: Message<V>) -> Vec<Message<V>> {
+        match message.body {
+            RequestBody::Prune { .. } => {
+                self.plumtree.make_lazy(&message.src);
+
+                vec![]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Flushes queued announcements and grafts announcers of values that
+    /// did not arrive through the tree before their deadline.
+    fn tree_tick(&mut self) -> Vec<Message<V>> {
+        let now = Instant::now();
+        let retry = now + self.config.graft_timeout / 2;
+
+        // values can also turn up through anti-entropy gossip
+        let delivered = &self.storage.messages;
+        self.plumtree
+            .missing
+            .retain(|value, _| !delivered.contains(value));
+
+        let mut grafts: HashMap<String, V::Set> = HashMap::new();
+        for (value, missing) in self.plumtree.missing.iter_mut() {
+            if missing.deadline > now {
+                continue;
+            }
+            if let Some(announcer) = missing.announcers.pop_front() {
+                grafts
+                    .entry(announcer.clone())
+                    .or_default()
+                    .insert(value.clone());
+                // try the same peer again last if nobody else delivers
+                missing.announcers.push_back(announcer);
+            }
+            missing.deadline = retry;
+        }
+
+        let mut to_send = Vec::new();
+        for (peer, messages) in grafts {
+            self.plumtree.make_eager(&peer);
+            to_send.push(Message {
+                src: self.id.clone(),
+                dest: peer,
+                body: RequestBody::Graft {
+                    msg_id: self.msg_id(),
+                    messages,
+                },
+            });
+        }
+
+        let announcements = std::mem::take(&mut self.plumtree.announcements);
+        for (peer, messages) in announcements {
+            let messages = match self.storage.known.get(&peer) {
+                Some(known) => messages.difference(known),
+                None => messages,
+            };
+            if messages.is_empty() {
+                continue;
+            }
+
+            to_send.push(Message {
+                src: self.id.clone(),
+                dest: peer,
+                body: RequestBody::IHave {
+                    msg_id: self.msg_id(),
+                    messages,
+                },
+            });
+        }
+
+        to_send
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Config, Strategy},
+        node::tests::{message, node, set},
+    };
+
+    fn tree_node(id: &str) -> Node<u64> {
+        let config = Config {
+            strategy: Strategy::Plumtree,
+            ..Config::default()
+        };
+        node(id, 3, config)
+    }
+
+    fn push(src: &str, values: &[u64]) -> Message<u64> {
+        let body = RequestBody::TreePush {
+            msg_id: 1,
+            messages: set(values),
+        };
+        message(src, "n0", body)
+    }
+
+    #[test]
+    fn duplicate_push_prunes_the_sender_to_lazy() {
+        let mut n0 = tree_node("n0");
+
+        let sent = n0.on_tree_push(push("n1", &[1]));
+        // passed on down the tree, but not back where it came from
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, "n2");
+        assert!(
+            matches!(&sent[0].body, RequestBody::TreePush { messages, .. } if *messages == set(&[1]))
+        );
+
+        let sent = n0.on_tree_push(push("n2", &[1]));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, "n2");
+        assert!(matches!(sent[0].body, RequestBody::Prune { .. }));
+        assert!(n0.plumtree.lazy.contains("n2"));
+        assert!(!n0.plumtree.eager.contains("n2"));
+
+        // n2 now only hears about new values in an ihave
+        let sent = n0.tree_broadcast(set(&[2]));
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, "n1");
+        let sent = n0.tree_tick();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, "n2");
+        assert!(
+            matches!(&sent[0].body, RequestBody::IHave { messages, .. } if *messages == set(&[2]))
+        );
+    }
+
+    #[test]
+    fn announced_value_that_does_not_arrive_is_grafted() {
+        let mut n0 = tree_node("n0");
+        let ihave = RequestBody::IHave {
+            msg_id: 1,
+            messages: set(&[5]),
+        };
+        n0.on_ihave(message("n1", "n0", ihave));
+        n0.plumtree.make_lazy("n1");
+
+        // the tree gets until the graft timeout to deliver it
+        assert!(n0.tree_tick().is_empty());
+
+        n0.plumtree.missing.get_mut(&5).unwrap().deadline = Instant::now();
+        let sent = n0.tree_tick();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, "n1");
+        assert!(
+            matches!(&sent[0].body, RequestBody::Graft { messages, .. } if *messages == set(&[5]))
+        );
+        assert!(n0.plumtree.eager.contains("n1"));
+    }
+
+    #[test]
+    fn announced_value_that_arrives_in_time_is_not_grafted() {
+        let mut n0 = tree_node("n0");
+        let ihave = RequestBody::IHave {
+            msg_id: 1,
+            messages: set(&[5]),
+        };
+        n0.on_ihave(message("n1", "n0", ihave));
+
+        n0.on_tree_push(push("n2", &[5]));
+
+        assert!(n0.plumtree.missing.is_empty());
+    }
+
+    #[test]
+    fn graft_makes_the_peer_eager_and_resends_the_value() {
+        let mut n0 = tree_node("n0");
+        n0.storage.add_message(3);
+        n0.tree_broadcast(set(&[]));
+        n0.plumtree.make_lazy("n1");
+
+        let graft = RequestBody::Graft {
+            msg_id: 1,
+            messages: set(&[3, 4]),
+        };
+        let sent = n0.on_graft(message("n1", "n0", graft));
+
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].dest, "n1");
+        // only what it has; 4 never reached it
+        assert!(
+            matches!(&sent[0].body, RequestBody::TreePush { messages, .. } if *messages == set(&[3]))
+        );
+        assert!(n0.plumtree.eager.contains("n1"));
+        assert!(!n0.plumtree.lazy.contains("n1"));
+    }
+}
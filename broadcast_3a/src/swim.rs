@@ -0,0 +1,634 @@
+//! SWIM failure detector (Das et al., "SWIM: Scalable Weakly-consistent
+//! Infection-style Process Group Membership Protocol").
+//!
+//! `Swim` holds the protocol state and knows nothing of the node around
+//! it: handlers take the fields of the SWIM messages and return the
+//! [`Outgoing`] messages to send, which the node wraps in its own envelope.
+//!
+//! Only the broadcast node runs it. kv leaves it out: a Raft leader already
+//! learns from its heartbeats whether a majority can reach it, and with
+//! check-quorum steps down when one cannot, so SWIM would add a second,
+//! possibly disagreeing, opinion on who is up.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use rand::seq::{IteratorRandom, SliceRandom};
+use serde::{Deserialize, Serialize};
+
+/// Upper bound on membership updates piggybacked onto one message.
+const MAX_PIGGYBACK: usize = 6;
+/// Each update is retransmitted `RETRANSMIT_MULTIPLIER * log2(n)` times.
+const RETRANSMIT_MULTIPLIER: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+/// A membership fact disseminated between nodes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberUpdate {
+    pub node: String,
+    pub health: Health,
+    pub incarnation: u64,
+}
+
+/// Timers and fan-out of the protocol.
+#[derive(Debug, Clone)]
+pub struct SwimConfig {
+    /// How often one member is probed.
+    pub protocol_period: Duration,
+    /// How long a direct `ping` may go unanswered before asking others.
+    pub ping_timeout: Duration,
+    /// How many members are asked to probe an unresponsive one.
+    pub indirect_probes: usize,
+    /// How long a member stays suspect before it is declared dead.
+    pub suspicion_timeout: Duration,
+}
+
+impl Default for SwimConfig {
+    fn default() -> Self {
+        SwimConfig {
+            protocol_period: Duration::from_millis(500),
+            ping_timeout: Duration::from_millis(150),
+            indirect_probes: 3,
+            suspicion_timeout: Duration::from_millis(2000),
+        }
+    }
+}
+
+/// A message the protocol wants sent to `dest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outgoing {
+    Ping {
+        dest: String,
+        msg_id: u64,
+        updates: Vec<MemberUpdate>,
+    },
+    PingOk {
+        dest: String,
+        in_reply_to: u64,
+        updates: Vec<MemberUpdate>,
+    },
+    PingReq {
+        dest: String,
+        msg_id: u64,
+        target: String,
+        updates: Vec<MemberUpdate>,
+    },
+    PingReqOk {
+        dest: String,
+        in_reply_to: u64,
+        target: String,
+        updates: Vec<MemberUpdate>,
+    },
+}
+
+#[derive(Debug)]
+struct Member {
+    health: Health,
+    incarnation: u64,
+    /// When the member became suspect.
+    suspected_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Probe {
+    target: String,
+    msg_id: u64,
+    started: Instant,
+    indirect: bool,
+    acked: bool,
+}
+
+/// A `ping` sent on behalf of another node's `ping_req`.
+#[derive(Debug)]
+struct Relay {
+    requester: String,
+    request_id: u64,
+    target: String,
+    started: Instant,
+}
+
+/// Each protocol period one member is pinged. If it does not answer within
+/// the ping timeout, `k` other members are asked to ping it on our behalf;
+/// if nobody gets an answer by the end of the period it becomes suspect, and
+/// a suspect that does not refute the suspicion with a higher incarnation is
+/// declared dead. Membership changes travel piggybacked on other messages.
+///
+/// Handlers that send new requests take `next_msg_id`, so the node's own
+/// msg_id counter stays the only one.
+#[derive(Debug, Default)]
+pub struct Swim {
+    id: String,
+    incarnation: u64,
+    members: HashMap<String, Member>,
+    /// Members left to probe this round, in random order.
+    probe_order: Vec<String>,
+    probe: Option<Probe>,
+    relays: HashMap<u64, Relay>,
+    /// Updates still to be piggybacked, with their remaining transmissions.
+    updates: Vec<(MemberUpdate, usize)>,
+}
+
+impl Swim {
+    /// Detector for node `me`, watching every other node of `peers`.
+    pub fn new(me: &str, peers: &[String]) -> Self {
+        let members = peers
+            .iter()
+            .filter(|peer| *peer != me)
+            .map(|peer| {
+                let member = Member {
+                    health: Health::Alive,
+                    incarnation: 0,
+                    suspected_at: None,
+                };
+                (peer.clone(), member)
+            })
+            .collect();
+
+        Swim {
+            id: me.to_string(),
+            members,
+            ..Swim::default()
+        }
+    }
+
+    /// Health of `peer` as currently believed by this node.
+    pub fn health(&self, peer: &str) -> Health {
+        self.members
+            .get(peer)
+            .map_or(Health::Alive, |member| member.health)
+    }
+
+    /// What this node believes about `peer`, as an update.
+    fn member_update(&self, peer: &str) -> Option<MemberUpdate> {
+        self.members.get(peer).map(|member| MemberUpdate {
+            node: peer.to_string(),
+            health: member.health,
+            incarnation: member.incarnation,
+        })
+    }
+
+    /// Takes the updates to attach to an outgoing message.
+    pub fn piggyback(&mut self) -> Vec<MemberUpdate> {
+        self.updates
+            .sort_by_key(|(_, remaining)| std::cmp::Reverse(*remaining));
+
+        let mut piggyback = Vec::new();
+        for (update, remaining) in self.updates.iter_mut().take(MAX_PIGGYBACK) {
+            piggyback.push(update.clone());
+            *remaining -= 1;
+        }
+        self.updates.retain(|(_, remaining)| *remaining > 0);
+
+        piggyback
+    }
+
+    fn disseminate(&mut self, update: MemberUpdate) {
+        let cluster_size = self.members.len() + 1;
+        let transmissions =
+            RETRANSMIT_MULTIPLIER * (usize::BITS - cluster_size.leading_zeros()) as usize;
+
+        self.updates
+            .retain(|(queued, _)| queued.node != update.node);
+        self.updates.push((update, transmissions.max(1)));
+    }
+
+    /// Applies updates received from another node, refuting any suspicion of
+    /// ourselves by bumping our incarnation.
+    pub fn apply(&mut self, updates: Vec<MemberUpdate>) {
+        for update in updates {
+            if update.node == self.id {
+                if update.health != Health::Alive && update.incarnation >= self.incarnation {
+                    self.incarnation = update.incarnation + 1;
+                    self.disseminate(MemberUpdate {
+                        node: self.id.clone(),
+                        health: Health::Alive,
+                        incarnation: self.incarnation,
+                    });
+                }
+                continue;
+            }
+
+            let Some(member) = self.members.get_mut(&update.node) else {
+                continue;
+            };
+
+            let overrides = match (update.health, member.health) {
+                (Health::Alive, _) => update.incarnation > member.incarnation,
+                (Health::Suspect, Health::Alive) => update.incarnation >= member.incarnation,
+                (Health::Suspect, _) => update.incarnation > member.incarnation,
+                (Health::Dead, Health::Dead) => false,
+                (Health::Dead, _) => update.incarnation >= member.incarnation,
+            };
+            if !overrides {
+                continue;
+            }
+
+            member.health = update.health;
+            member.incarnation = update.incarnation;
+            member.suspected_at = match update.health {
+                Health::Suspect => Some(Instant::now()),
+                _ => None,
+            };
+            self.disseminate(update);
+        }
+    }
+
+    fn suspect(&mut self, peer: &str) {
+        let Some(member) = self.members.get_mut(peer) else {
+            return;
+        };
+        if member.health != Health::Alive {
+            return;
+        }
+
+        member.health = Health::Suspect;
+        member.suspected_at = Some(Instant::now());
+        let update = MemberUpdate {
+            node: peer.to_string(),
+            health: Health::Suspect,
+            incarnation: member.incarnation,
+        };
+        self.disseminate(update);
+    }
+
+    fn expire_suspects(&mut self, suspicion_timeout: Duration) {
+        let expired: Vec<(String, u64)> = self
+            .members
+            .iter()
+            .filter(|(_, member)| {
+                member
+                    .suspected_at
+                    .is_some_and(|at| at.elapsed() >= suspicion_timeout)
+            })
+            .map(|(peer, member)| (peer.clone(), member.incarnation))
+            .collect();
+
+        for (peer, incarnation) in expired {
+            if let Some(member) = self.members.get_mut(&peer) {
+                member.health = Health::Dead;
+                member.suspected_at = None;
+            }
+            self.disseminate(MemberUpdate {
+                node: peer,
+                health: Health::Dead,
+                incarnation,
+            });
+        }
+    }
+
+    fn next_probe_target(&mut self) -> Option<String> {
+        if self.probe_order.is_empty() {
+            // dead members are still probed, that is how healed partitions
+            // are noticed
+            self.probe_order = self.members.keys().cloned().collect();
+            self.probe_order.shuffle(&mut rand::thread_rng());
+        }
+        self.probe_order.pop()
+    }
+
+    fn ping(&mut self, target: String, msg_id: u64) -> Outgoing {
+        let mut updates = self.piggyback();
+        // a member we think is down has to hear about it to refute it
+        if let Some(status) = self.member_update(&target) {
+            if status.health != Health::Alive && !updates.contains(&status) {
+                updates.push(status);
+            }
+        }
+
+        Outgoing::Ping {
+            dest: target,
+            msg_id,
+            updates,
+        }
+    }
+
+    pub fn on_ping(&mut self, src: &str, msg_id: u64, updates: Vec<MemberUpdate>) -> Outgoing {
+        self.apply(updates);
+
+        Outgoing::PingOk {
+            dest: src.to_string(),
+            in_reply_to: msg_id,
+            updates: self.piggyback(),
+        }
+    }
+
+    /// Completes our probe of `src`, or relays the answer to whoever asked
+    /// us to ping it.
+    pub fn on_ping_ok(
+        &mut self,
+        src: &str,
+        in_reply_to: u64,
+        updates: Vec<MemberUpdate>,
+    ) -> Option<Outgoing> {
+        self.apply(updates);
+
+        if let Some(probe) = &mut self.probe {
+            if probe.msg_id == in_reply_to && probe.target == src {
+                probe.acked = true;
+            }
+        }
+
+        match self.relays.remove(&in_reply_to) {
+            Some(relay) if relay.target == src => Some(Outgoing::PingReqOk {
+                dest: relay.requester,
+                in_reply_to: relay.request_id,
+                target: relay.target,
+                updates: self.piggyback(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Pings `target` on behalf of `src`.
+    pub fn on_ping_req(
+        &mut self,
+        src: &str,
+        msg_id: u64,
+        target: String,
+        updates: Vec<MemberUpdate>,
+        next_msg_id: &mut impl FnMut() -> u64,
+    ) -> Outgoing {
+        self.apply(updates);
+
+        let ping_id = next_msg_id();
+        self.relays.insert(
+            ping_id,
+            Relay {
+                requester: src.to_string(),
+                request_id: msg_id,
+                target: target.clone(),
+                started: Instant::now(),
+            },
+        );
+
+        self.ping(target, ping_id)
+    }
+
+    pub fn on_ping_req_ok(&mut self, target: &str, updates: Vec<MemberUpdate>) {
+        self.apply(updates);
+
+        if let Some(probe) = &mut self.probe {
+            if probe.target == target {
+                probe.acked = true;
+            }
+        }
+    }
+
+    /// Advances the current probe and starts the next one once a protocol
+    /// period has passed.
+    pub fn tick(
+        &mut self,
+        config: &SwimConfig,
+        next_msg_id: &mut impl FnMut() -> u64,
+    ) -> Vec<Outgoing> {
+        let mut to_send = Vec::new();
+        let period = config.protocol_period;
+
+        self.expire_suspects(config.suspicion_timeout);
+        self.relays
+            .retain(|_, relay| relay.started.elapsed() < period);
+
+        if let Some(probe) = &self.probe {
+            let elapsed = probe.started.elapsed();
+
+            if !probe.acked && !probe.indirect && elapsed >= config.ping_timeout {
+                let target = probe.target.clone();
+                let helpers: Vec<String> = self
+                    .members
+                    .iter()
+                    .filter(|(peer, member)| **peer != target && member.health == Health::Alive)
+                    .map(|(peer, _)| peer.clone())
+                    .choose_multiple(&mut rand::thread_rng(), config.indirect_probes);
+
+                for helper in helpers {
+                    to_send.push(Outgoing::PingReq {
+                        dest: helper,
+                        msg_id: next_msg_id(),
+                        target: target.clone(),
+                        updates: self.piggyback(),
+                    });
+                }
+                if let Some(probe) = &mut self.probe {
+                    probe.indirect = true;
+                }
+            }
+
+            if elapsed < period {
+                return to_send;
+            }
+
+            if let Some(probe) = self.probe.take() {
+                if !probe.acked {
+                    self.suspect(&probe.target);
+                }
+            }
+        }
+
+        if let Some(target) = self.next_probe_target() {
+            let msg_id = next_msg_id();
+            to_send.push(self.ping(target.clone(), msg_id));
+            self.probe = Some(Probe {
+                target,
+                msg_id,
+                started: Instant::now(),
+                indirect: false,
+                acked: false,
+            });
+        }
+
+        to_send
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn swim(me: &str, size: usize) -> Swim {
+        let peers: Vec<String> = (0..size).map(|i| format!("n{}", i)).collect();
+        Swim::new(me, &peers)
+    }
+
+    fn counter() -> impl FnMut() -> u64 {
+        let mut next = 0;
+        move || {
+            next += 1;
+            next
+        }
+    }
+
+    fn update(node: &str, health: Health, incarnation: u64) -> MemberUpdate {
+        MemberUpdate {
+            node: node.to_string(),
+            health,
+            incarnation,
+        }
+    }
+
+    #[test]
+    fn unanswered_ping_is_followed_by_ping_reqs() {
+        let config = SwimConfig {
+            protocol_period: Duration::from_secs(60),
+            ping_timeout: Duration::from_millis(1),
+            indirect_probes: 2,
+            ..SwimConfig::default()
+        };
+        let mut swim = swim("n0", 4);
+        let mut next_msg_id = counter();
+
+        let sent = swim.tick(&config, &mut next_msg_id);
+        let [Outgoing::Ping { dest: target, .. }] = &sent[..] else {
+            panic!("expected one ping, got {:?}", sent);
+        };
+
+        thread::sleep(Duration::from_millis(2));
+        let sent = swim.tick(&config, &mut next_msg_id);
+        assert_eq!(sent.len(), 2);
+        for outgoing in &sent {
+            let Outgoing::PingReq {
+                dest,
+                target: asked_about,
+                ..
+            } = outgoing
+            else {
+                panic!("expected a ping_req, got {:?}", outgoing);
+            };
+            assert_ne!(dest, target);
+            assert_eq!(asked_about, target);
+        }
+
+        // the helpers are only asked once per probe
+        assert!(swim.tick(&config, &mut next_msg_id).is_empty());
+    }
+
+    #[test]
+    fn ping_req_is_answered_once_the_target_answers() {
+        let mut swim = swim("n1", 3);
+        let mut next_msg_id = counter();
+
+        let ping = swim.on_ping_req("n0", 7, "n2".to_string(), vec![], &mut next_msg_id);
+        let Outgoing::Ping { dest, msg_id, .. } = ping else {
+            panic!("expected a ping, got {:?}", ping);
+        };
+        assert_eq!(dest, "n2");
+
+        let relayed = swim.on_ping_ok("n2", msg_id, vec![]);
+        assert!(matches!(
+            relayed,
+            Some(Outgoing::PingReqOk { dest, in_reply_to: 7, target, .. })
+                if dest == "n0" && target == "n2"
+        ));
+    }
+
+    #[test]
+    fn unanswered_member_is_suspected_then_declared_dead() {
+        let config = SwimConfig {
+            protocol_period: Duration::from_millis(5),
+            ping_timeout: Duration::from_millis(1),
+            indirect_probes: 0,
+            suspicion_timeout: Duration::from_millis(10),
+        };
+        let mut swim = swim("n0", 2);
+        let mut next_msg_id = counter();
+
+        swim.tick(&config, &mut next_msg_id);
+        thread::sleep(config.protocol_period);
+        swim.tick(&config, &mut next_msg_id);
+        assert_eq!(swim.health("n1"), Health::Suspect);
+
+        thread::sleep(config.suspicion_timeout);
+        swim.tick(&config, &mut next_msg_id);
+        assert_eq!(swim.health("n1"), Health::Dead);
+        assert!(swim.piggyback().contains(&update("n1", Health::Dead, 0)));
+    }
+
+    #[test]
+    fn answered_member_stays_alive() {
+        let config = SwimConfig {
+            protocol_period: Duration::from_millis(5),
+            ..SwimConfig::default()
+        };
+        let mut swim = swim("n0", 2);
+        let mut next_msg_id = counter();
+
+        let sent = swim.tick(&config, &mut next_msg_id);
+        let [Outgoing::Ping { msg_id, .. }] = sent[..] else {
+            panic!("expected one ping, got {:?}", sent);
+        };
+        swim.on_ping_ok("n1", msg_id, vec![]);
+
+        thread::sleep(config.protocol_period);
+        swim.tick(&config, &mut next_msg_id);
+        assert_eq!(swim.health("n1"), Health::Alive);
+    }
+
+    #[test]
+    fn suspicion_is_refuted_by_a_higher_incarnation() {
+        let mut swim = swim("n0", 3);
+
+        swim.apply(vec![update("n1", Health::Suspect, 0)]);
+        assert_eq!(swim.health("n1"), Health::Suspect);
+
+        // an alive claim from the incarnation it was suspected in is stale
+        swim.apply(vec![update("n1", Health::Alive, 0)]);
+        assert_eq!(swim.health("n1"), Health::Suspect);
+
+        swim.apply(vec![update("n1", Health::Alive, 1)]);
+        assert_eq!(swim.health("n1"), Health::Alive);
+    }
+
+    #[test]
+    fn suspected_node_refutes_by_bumping_its_incarnation() {
+        let mut swim = swim("n0", 3);
+
+        swim.apply(vec![update("n0", Health::Suspect, 0)]);
+
+        assert_eq!(swim.incarnation, 1);
+        assert!(swim.piggyback().contains(&update("n0", Health::Alive, 1)));
+    }
+
+    #[test]
+    fn updates_spread_piggybacked_on_pings() {
+        let mut n0 = swim("n0", 3);
+        let mut n1 = swim("n1", 3);
+        n0.apply(vec![update("n2", Health::Suspect, 0)]);
+
+        let ping = n0.ping("n1".to_string(), 1);
+        let Outgoing::Ping {
+            msg_id, updates, ..
+        } = ping
+        else {
+            panic!("expected a ping, got {:?}", ping);
+        };
+        let ack = n1.on_ping("n0", msg_id, updates);
+
+        assert_eq!(n1.health("n2"), Health::Suspect);
+        // and n1 passes it on in turn
+        let Outgoing::PingOk { updates, .. } = ack else {
+            panic!("expected a ping_ok, got {:?}", ack);
+        };
+        assert!(updates.contains(&update("n2", Health::Suspect, 0)));
+    }
+
+    #[test]
+    fn updates_are_piggybacked_a_bounded_number_of_times() {
+        let mut swim = swim("n0", 4);
+        swim.apply(vec![update("n1", Health::Suspect, 0)]);
+
+        let sent = (0..100).filter(|_| !swim.piggyback().is_empty()).count();
+
+        // 4 nodes take 3 bits to count
+        assert_eq!(sent, RETRANSMIT_MULTIPLIER * 3);
+    }
+}
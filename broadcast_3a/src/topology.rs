@@ -0,0 +1,24 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+/// The cluster layout handed out by Maelstrom's `topology` message.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Topology {
+    neighbours: HashMap<String, Vec<String>>,
+}
+
+impl Topology {
+    pub fn init(&mut self, topology: HashMap<String, Vec<String>>) {
+        self.neighbours = topology;
+    }
+
+    /// Neighbours of `node`: its entry in the topology once one has been
+    /// received, every other node in `nodes` until then.
+    pub fn neighbours(&self, node: &str, nodes: &[String]) -> Vec<String> {
+        match self.neighbours.get(node) {
+            Some(neighbours) => neighbours.clone(),
+            None => nodes.iter().filter(|peer| *peer != node).cloned().collect(),
+        }
+    }
+}
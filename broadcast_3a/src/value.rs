@@ -0,0 +1,102 @@
+use std::{collections::HashSet, fmt::Debug, hash::Hash};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::message_set::MessageSet;
+
+/// A value clients can broadcast.
+///
+/// Each value type picks the set it is stored and gossiped in, so integers
+/// keep their range-compressed encoding while anything else falls back to a
+/// plain hash set.
+pub trait Value: Clone + Debug + Eq + Hash + Serialize + DeserializeOwned + Send + 'static {
+    type Set: ValueSet<Self>;
+}
+
+/// The set operations the gossip and tree machinery need.
+pub trait ValueSet<V>:
+    Debug + Default + Clone + PartialEq + Serialize + DeserializeOwned + Send
+{
+    /// Inserts `value`, returning `false` if it was already present.
+    fn insert(&mut self, value: V) -> bool;
+    fn contains(&self, value: &V) -> bool;
+    /// Adds every value of `other` to this set.
+    fn union(&mut self, other: &Self);
+    /// Returns the values of this set that are not in `other`.
+    fn difference(&self, other: &Self) -> Self;
+    /// Returns the values present in both sets.
+    fn intersection(&self, other: &Self) -> Self;
+    fn len(&self) -> u64;
+    /// Returns at most `limit` values of this set.
+    fn truncated(&self, limit: u64) -> Self;
+    fn is_empty(&self) -> bool;
+    fn values(&self) -> Vec<V>;
+}
+
+impl Value for u64 {
+    type Set = MessageSet;
+}
+
+impl Value for String {
+    type Set = HashedSet<String>;
+}
+
+impl Value for serde_json::Value {
+    type Set = HashedSet<serde_json::Value>;
+}
+
+/// Set of arbitrary values, serialized as a JSON array.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "V: Serialize",
+    deserialize = "V: DeserializeOwned + Eq + Hash"
+))]
+pub struct HashedSet<V: Eq + Hash>(HashSet<V>);
+
+impl<V: Eq + Hash> Default for HashedSet<V> {
+    fn default() -> Self {
+        HashedSet(HashSet::new())
+    }
+}
+
+impl<V> ValueSet<V> for HashedSet<V>
+where
+    V: Clone + Debug + Eq + Hash + Serialize + DeserializeOwned + Send,
+{
+    fn insert(&mut self, value: V) -> bool {
+        self.0.insert(value)
+    }
+
+    fn contains(&self, value: &V) -> bool {
+        self.0.contains(value)
+    }
+
+    fn union(&mut self, other: &Self) {
+        self.0.extend(other.0.iter().cloned());
+    }
+
+    fn difference(&self, other: &Self) -> Self {
+        HashedSet(self.0.difference(&other.0).cloned().collect())
+    }
+
+    fn intersection(&self, other: &Self) -> Self {
+        HashedSet(self.0.intersection(&other.0).cloned().collect())
+    }
+
+    fn len(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    fn truncated(&self, limit: u64) -> Self {
+        let limit = usize::try_from(limit).unwrap_or(usize::MAX);
+        HashedSet(self.0.iter().take(limit).cloned().collect())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn values(&self) -> Vec<V> {
+        self.0.iter().cloned().collect()
+    }
+}
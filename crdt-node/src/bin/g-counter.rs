@@ -0,0 +1,5 @@
+use crdt::GCounter;
+
+fn main() {
+    crdt_node::run::<GCounter>();
+}
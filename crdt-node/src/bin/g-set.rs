@@ -0,0 +1,5 @@
+use crdt::GSet;
+
+fn main() {
+    crdt_node::run::<GSet<i64>>();
+}
@@ -0,0 +1,5 @@
+use crdt::PnCounter;
+
+fn main() {
+    crdt_node::run::<PnCounter>();
+}
@@ -0,0 +1,30 @@
+//! A Maelstrom node that replicates any delta-state CRDT by periodically
+//! sending its neighbours the deltas they have not acknowledged yet. Each
+//! workload binary only says which CRDT to run, see `src/bin`.
+
+mod node;
+mod workload;
+
+pub use node::run;
+pub use workload::Workload;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Body<Payload> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    msg_id: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    in_reply_to: Option<u64>,
+
+    #[serde(flatten)]
+    payload: Payload,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Message<Payload> {
+    pub src: String,
+    pub dest: String,
+    pub body: Body<Payload>,
+}
@@ -0,0 +1,648 @@
+use std::{
+    collections::HashMap,
+    env,
+    io::{stdin, BufRead, Write},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use broadcast_3a::topology::Topology;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Body, Message, Workload};
+
+const DEFAULT_REPLICATE_INTERVAL: Duration = Duration::from_millis(250);
+/// Deltas kept for neighbours that have not acknowledged them. Past this a
+/// neighbour that fell behind, e.g. across a partition, gets the full state
+/// again instead.
+const MAX_DELTAS: usize = 1000;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[serde(bound = "W: Workload, W::Value: Serialize + DeserializeOwned")]
+pub enum Payload<W: Workload> {
+    Init {
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+    InitOk {},
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk {},
+    Add {
+        #[serde(flatten)]
+        add: W::Add,
+    },
+    AddOk {},
+    Read {},
+    ReadOk {
+        value: W::Value,
+    },
+    /// The sender's whole state, covering its deltas up to `seq`. Sent until
+    /// the receiver has acknowledged something.
+    Replicate {
+        state: W,
+        seq: u64,
+        epoch: u64,
+    },
+    /// The join of the sender's deltas the receiver has not acknowledged,
+    /// up to `seq`.
+    ReplicateDelta {
+        delta: W,
+        seq: u64,
+        epoch: u64,
+    },
+    /// Acknowledges the deltas up to `seq` the sender numbered in `epoch`.
+    ReplicateOk {
+        seq: u64,
+        epoch: u64,
+    },
+}
+
+/// A delta in the log, with the node it came from.
+#[derive(Debug)]
+struct Delta<W> {
+    seq: u64,
+    origin: String,
+    delta: W,
+}
+
+#[derive(Debug, Default)]
+struct ReplicationNode<W> {
+    id: String,
+    peers: Vec<String>,
+    /// Picked afresh each time the node starts. Sequence numbers start over
+    /// along with it, so they are only compared within one epoch.
+    epoch: u64,
+    topology: Topology,
+    state: W,
+    /// Deltas made here or received from others that changed our state, in
+    /// order. Deltas every neighbour has acknowledged are dropped.
+    deltas: Vec<Delta<W>>,
+    next_seq: u64,
+    /// Highest delta sequence number each neighbour has acknowledged. A
+    /// neighbour missing here gets the full state.
+    acked: HashMap<String, u64>,
+    /// Epoch each neighbour last replicated to us in. A new one means the
+    /// neighbour restarted and lost what it had.
+    heard: HashMap<String, u64>,
+}
+
+impl<W> ReplicationNode<W>
+where
+    W: Workload,
+    W::Value: Serialize + DeserializeOwned,
+{
+    fn step(&mut self, input: Message<Payload<W>>) -> Option<Message<Payload<W>>> {
+        let payload = match input.body.payload {
+            Payload::Init { node_id, node_ids } => {
+                self.id = node_id;
+                self.peers = node_ids;
+                self.epoch = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("the clock is past 1970")
+                    .as_nanos() as u64;
+                Payload::InitOk {}
+            }
+            Payload::Topology { topology } => {
+                self.topology.init(topology);
+                Payload::TopologyOk {}
+            }
+            Payload::Add { add } => {
+                let delta = self.state.add(&self.id, add);
+                let me = self.id.clone();
+                self.record(me, delta);
+                Payload::AddOk {}
+            }
+            Payload::Read {} => Payload::ReadOk {
+                value: self.state.value(),
+            },
+            Payload::Replicate {
+                state: delta,
+                seq,
+                epoch,
+            }
+            | Payload::ReplicateDelta { delta, seq, epoch } => {
+                self.heard_from(&input.src, epoch);
+                self.receive(input.src.clone(), delta);
+                Payload::ReplicateOk { seq, epoch }
+            }
+            Payload::ReplicateOk { seq, epoch } => {
+                // an answer to something we sent before we restarted
+                if epoch != self.epoch {
+                    return None;
+                }
+                let acked = self.acked.entry(input.src).or_default();
+                *acked = (*acked).max(seq);
+                self.compact();
+                return None;
+            }
+            _ => panic!("invalid type"),
+        };
+
+        Some(Message {
+            src: self.id.clone(),
+            dest: input.src,
+            body: Body {
+                msg_id: input.body.msg_id,
+                in_reply_to: input.body.msg_id,
+                payload,
+            },
+        })
+    }
+
+    /// Notes the epoch `peer` replicates in, starting over with its full
+    /// state when that changed. Sequence numbers are not compared, as
+    /// replication messages can be reordered or duplicated.
+    fn heard_from(&mut self, peer: &str, epoch: u64) {
+        let previous = self.heard.insert(peer.to_string(), epoch);
+        if previous.is_some_and(|previous| previous != epoch) {
+            self.acked.remove(peer);
+        }
+    }
+
+    fn record(&mut self, origin: String, delta: W) {
+        self.next_seq += 1;
+        self.deltas.push(Delta {
+            seq: self.next_seq,
+            origin,
+            delta,
+        });
+    }
+
+    /// Merges a state or delta from `origin`, keeping it for our other
+    /// neighbours if it told us anything new.
+    fn receive(&mut self, origin: String, delta: W) {
+        let mut merged = self.state.clone();
+        merged.merge(&delta);
+        if merged != self.state {
+            self.state = merged;
+            self.record(origin, delta);
+        }
+    }
+
+    /// Drops the deltas every neighbour has acknowledged, and the oldest
+    /// ones past `MAX_DELTAS`. Neighbours still waiting for those are sent
+    /// the full state instead.
+    fn compact(&mut self) {
+        let neighbours = self.topology.neighbours(&self.id, &self.peers);
+        let acked = neighbours
+            .iter()
+            .map(|neighbour| self.acked.get(neighbour).copied().unwrap_or_default())
+            .min()
+            .unwrap_or(self.next_seq);
+        self.deltas.retain(|delta| delta.seq > acked);
+
+        let excess = self.deltas.len().saturating_sub(MAX_DELTAS);
+        if excess > 0 {
+            let kept_from = self.deltas[excess].seq;
+            self.deltas.drain(..excess);
+            self.acked.retain(|_, acked| *acked + 1 >= kept_from);
+        }
+    }
+
+    fn replicate(&mut self) -> Vec<Message<Payload<W>>> {
+        let mut to_send = Vec::new();
+
+        for neighbour in self.topology.neighbours(&self.id, &self.peers) {
+            let payload = match self.acked.get(&neighbour).copied() {
+                Some(acked) if acked >= self.next_seq => continue,
+                Some(acked) => {
+                    let mut group: Option<W> = None;
+                    for delta in &self.deltas {
+                        if delta.seq <= acked || delta.origin == neighbour {
+                            continue;
+                        }
+                        match &mut group {
+                            Some(group) => group.merge(&delta.delta),
+                            None => group = Some(delta.delta.clone()),
+                        }
+                    }
+
+                    match group {
+                        Some(delta) => Payload::ReplicateDelta {
+                            delta,
+                            seq: self.next_seq,
+                            epoch: self.epoch,
+                        },
+                        None => {
+                            // all of it came from the neighbour itself
+                            self.acked.insert(neighbour, self.next_seq);
+                            continue;
+                        }
+                    }
+                }
+                None => Payload::Replicate {
+                    state: self.state.clone(),
+                    seq: self.next_seq,
+                    epoch: self.epoch,
+                },
+            };
+
+            to_send.push(Message {
+                src: self.id.clone(),
+                dest: neighbour,
+                body: Body {
+                    msg_id: None,
+                    in_reply_to: None,
+                    payload,
+                },
+            });
+        }
+
+        self.compact();
+        to_send
+    }
+}
+
+fn read_from_stdin<Payload: DeserializeOwned>(tx: Sender<Message<Payload>>) {
+    for line in stdin().lock().lines() {
+        let message = serde_json::from_str::<Message<Payload>>(&line.unwrap()).unwrap();
+        tx.send(message).unwrap();
+    }
+}
+
+fn write_to_stdout<Payload: Serialize>(message: Message<Payload>) {
+    let mut stdout = std::io::stdout();
+    let output = serde_json::to_string(&message).unwrap();
+    writeln!(stdout, "{}", output).unwrap();
+    stdout.flush().unwrap();
+}
+
+/// Runs a node replicating `W` until stdin closes. The state is sent to
+/// every neighbour each `CRDT_REPLICATE_INTERVAL_MS` milliseconds.
+pub fn run<W>()
+where
+    W: Workload + Send + 'static,
+    W::Add: Send,
+    W::Value: Serialize + DeserializeOwned + Send,
+{
+    let interval = match env::var("CRDT_REPLICATE_INTERVAL_MS") {
+        Ok(ms) => Duration::from_millis(
+            ms.parse()
+                .unwrap_or_else(|_| panic!("invalid value for CRDT_REPLICATE_INTERVAL_MS: {}", ms)),
+        ),
+        Err(_) => DEFAULT_REPLICATE_INTERVAL,
+    };
+
+    let mut node = ReplicationNode::<W>::default();
+
+    let (reader_tx, reader_rx) = mpsc::channel::<Message<Payload<W>>>();
+    thread::spawn(move || read_from_stdin(reader_tx));
+
+    let mut last_replicated = Instant::now();
+    loop {
+        let timeout = interval.saturating_sub(last_replicated.elapsed());
+
+        match reader_rx.recv_timeout(timeout) {
+            Ok(message) => {
+                if let Some(reply) = node.step(message) {
+                    write_to_stdout(reply);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_replicated.elapsed() >= interval {
+            for message in node.replicate() {
+                write_to_stdout(message);
+            }
+            last_replicated = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crdt::GSet;
+
+    use super::*;
+
+    type Node = ReplicationNode<GSet<i64>>;
+
+    fn message(src: &str, dest: &str, payload: Payload<GSet<i64>>) -> Message<Payload<GSet<i64>>> {
+        Message {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body: Body {
+                msg_id: None,
+                in_reply_to: None,
+                payload,
+            },
+        }
+    }
+
+    /// Nodes n0, n1, ... all neighbouring each other, as no topology is
+    /// given.
+    struct Network {
+        nodes: Vec<Node>,
+    }
+
+    impl Network {
+        fn new(size: usize) -> Self {
+            let ids: Vec<String> = (0..size).map(|i| format!("n{}", i)).collect();
+            let nodes = ids
+                .iter()
+                .map(|id| {
+                    let mut node = Node::default();
+                    let init = Payload::Init {
+                        node_id: id.clone(),
+                        node_ids: ids.clone(),
+                    };
+                    node.step(message("c0", id, init));
+                    node
+                })
+                .collect();
+            Network { nodes }
+        }
+
+        fn node(&mut self, id: &str) -> &mut Node {
+            self.nodes.iter_mut().find(|node| node.id == id).unwrap()
+        }
+
+        fn add(&mut self, id: &str, element: i64) {
+            let add = Payload::Add {
+                add: serde_json::from_value(serde_json::json!({ "element": element })).unwrap(),
+            };
+            self.node(id).step(message("c1", id, add));
+        }
+
+        /// Has every node replicate once, delivering what `deliver` lets
+        /// through along with the acknowledgements, and returns what was
+        /// sent.
+        fn round(
+            &mut self,
+            mut deliver: impl FnMut(&Message<Payload<GSet<i64>>>) -> bool,
+        ) -> Vec<Message<Payload<GSet<i64>>>> {
+            let sent: Vec<_> = self
+                .nodes
+                .iter_mut()
+                .flat_map(|node| node.replicate())
+                .collect();
+            for message in &sent {
+                if !deliver(message) {
+                    continue;
+                }
+                // through JSON, as on the wire
+                let message: Message<Payload<GSet<i64>>> =
+                    serde_json::from_str(&serde_json::to_string(message).unwrap()).unwrap();
+                let dest = message.dest.clone();
+                let ack = self.node(&dest).step(message);
+                if let Some(ack) = ack.filter(|ack| deliver(ack)) {
+                    let dest = ack.dest.clone();
+                    self.node(&dest).step(ack);
+                }
+            }
+            sent
+        }
+
+        fn states(&self) -> Vec<&GSet<i64>> {
+            self.nodes.iter().map(|node| &node.state).collect()
+        }
+    }
+
+    fn everything(_: &Message<Payload<GSet<i64>>>) -> bool {
+        true
+    }
+
+    /// What was sent to `dest`, if anything.
+    fn sent_to<'a>(
+        sent: &'a [Message<Payload<GSet<i64>>>],
+        dest: &str,
+    ) -> Option<&'a Payload<GSet<i64>>> {
+        sent.iter()
+            .find(|message| message.dest == dest)
+            .map(|message| &message.body.payload)
+    }
+
+    fn set(elements: impl IntoIterator<Item = i64>) -> GSet<i64> {
+        let mut set = GSet::default();
+        for element in elements {
+            set.insert(element);
+        }
+        set
+    }
+
+    #[test]
+    fn reordered_replication_does_not_look_like_a_restart() {
+        let mut network = Network::new(2);
+        network.add("n0", 1);
+        network.round(everything);
+        network.add("n1", 2);
+        network.round(everything);
+        let acked = network.node("n1").acked.get("n0").copied();
+        assert!(acked.is_some());
+
+        // n0's first full state turning up again late
+        let epoch = network.node("n0").epoch;
+        let stale = Payload::Replicate {
+            state: set([1]),
+            seq: 1,
+            epoch,
+        };
+        network.node("n1").step(message("n0", "n1", stale));
+
+        assert_eq!(network.node("n1").acked.get("n0").copied(), acked);
+    }
+
+    #[test]
+    fn restarted_neighbour_is_sent_the_full_state_again() {
+        let mut network = Network::new(2);
+        network.add("n0", 1);
+        network.round(everything);
+        network.round(everything);
+        network.add("n0", 2);
+        assert!(matches!(
+            sent_to(&network.round(everything), "n1"),
+            Some(Payload::ReplicateDelta { .. })
+        ));
+
+        let ids = network.node("n1").peers.clone();
+        let mut restarted = Node::default();
+        let init = Payload::Init {
+            node_id: "n1".to_string(),
+            node_ids: ids,
+        };
+        restarted.step(message("c0", "n1", init));
+        restarted.epoch = network.node("n1").epoch + 1;
+        *network.node("n1") = restarted;
+
+        // an answer to n0 from before the restart does not count either
+        let stale_epoch = network.node("n0").epoch - 1;
+        let stale_ack = Payload::ReplicateOk {
+            seq: 100,
+            epoch: stale_epoch,
+        };
+        network.node("n0").step(message("n1", "n0", stale_ack));
+
+        network.round(everything);
+        let sent = network.round(everything);
+        assert!(matches!(
+            sent_to(&sent, "n1"),
+            Some(Payload::Replicate { .. })
+        ));
+        assert_eq!(network.node("n1").state, set([1, 2]));
+    }
+
+    #[test]
+    fn partitioned_neighbour_falls_back_to_the_full_state() {
+        let mut network = Network::new(3);
+        network.add("n0", 0);
+        network.round(everything);
+        network.round(everything);
+
+        let cut_off =
+            |message: &Message<Payload<GSet<i64>>>| message.src != "n2" && message.dest != "n2";
+        for element in 1..=MAX_DELTAS as i64 + 10 {
+            network.add("n0", element);
+            network.round(cut_off);
+        }
+        assert!(network.node("n0").deltas.len() <= MAX_DELTAS);
+        assert!(!network.node("n0").acked.contains_key("n2"));
+
+        let sent = network.round(everything);
+        assert!(matches!(
+            sent_to(&sent, "n2"),
+            Some(Payload::Replicate { .. })
+        ));
+        let states = network.states();
+        assert!(states.iter().all(|state| *state == states[0]));
+    }
+
+    #[test]
+    fn new_neighbour_is_sent_the_full_state() {
+        let mut network = Network::new(3);
+        network.add("n0", 1);
+        network.add("n0", 2);
+
+        let sent = network.round(everything);
+
+        for peer in ["n1", "n2"] {
+            assert!(matches!(
+                sent_to(&sent, peer),
+                Some(Payload::Replicate { state, .. }) if *state == set([1, 2])
+            ));
+        }
+        assert!(network.states().iter().all(|state| **state == set([1, 2])));
+    }
+
+    #[test]
+    fn nodes_converge_despite_lost_messages() {
+        let mut network = Network::new(3);
+        let mut count = 0;
+        // every third message, replication and acks alike, goes missing
+        let mut lossy = |_: &Message<Payload<GSet<i64>>>| {
+            count += 1;
+            count % 3 != 0
+        };
+
+        for element in 0..30 {
+            network.add(&format!("n{}", element % 3), element);
+            network.round(&mut lossy);
+        }
+        for _ in 0..5 {
+            network.round(&mut lossy);
+        }
+
+        assert!(network.states().iter().all(|state| **state == set(0..30)));
+    }
+
+    /// A network of `size` nodes that all hold `elements` and know it.
+    fn synced(size: usize, elements: impl IntoIterator<Item = i64>) -> Network {
+        let mut network = Network::new(size);
+        for element in elements {
+            network.add("n0", element);
+        }
+        network.round(everything);
+        network.round(everything);
+        network
+    }
+
+    #[test]
+    fn acknowledged_neighbours_are_sent_only_new_deltas() {
+        let mut network = synced(3, [1, 2]);
+        assert!(network.round(everything).is_empty());
+
+        network.add("n0", 3);
+        let sent = network.round(everything);
+
+        for peer in ["n1", "n2"] {
+            assert!(matches!(
+                sent_to(&sent, peer),
+                Some(Payload::ReplicateDelta { delta, .. }) if *delta == set([3])
+            ));
+        }
+        // n1 and n2 pass 3 on to each other but not back to n0
+        assert!(sent.iter().all(|message| message.dest != "n0"));
+        assert!(network
+            .states()
+            .iter()
+            .all(|state| **state == set([1, 2, 3])));
+        assert!(network.node("n0").deltas.is_empty());
+    }
+
+    #[test]
+    fn unacknowledged_deltas_are_sent_again() {
+        let mut network = synced(2, [1]);
+
+        network.add("n0", 2);
+        network.round(|_| false);
+        network.add("n0", 3);
+        let sent = network.round(everything);
+
+        assert!(matches!(
+            sent_to(&sent, "n1"),
+            Some(Payload::ReplicateDelta { delta, .. }) if *delta == set([2, 3])
+        ));
+        assert_eq!(network.node("n1").state, set([1, 2, 3]));
+
+        // the reply was lost, so the next round repeats it
+        network.add("n0", 4);
+        let sent =
+            network.round(|message| !matches!(message.body.payload, Payload::ReplicateOk { .. }));
+        assert!(matches!(
+            sent_to(&sent, "n1"),
+            Some(Payload::ReplicateDelta { delta, .. }) if *delta == set([4])
+        ));
+        let sent = network.round(everything);
+        assert!(matches!(
+            sent_to(&sent, "n1"),
+            Some(Payload::ReplicateDelta { delta, .. }) if *delta == set([4])
+        ));
+    }
+
+    #[test]
+    fn deltas_are_much_smaller_than_the_full_state() {
+        let mut network = synced(2, 0..1000);
+
+        network.add("n0", 1000);
+        let sent = network.round(everything);
+        let delta = serde_json::to_string(&sent[0]).unwrap();
+        assert!(matches!(
+            sent[0].body.payload,
+            Payload::ReplicateDelta { .. }
+        ));
+
+        let n0 = network.node("n0");
+        let full = message(
+            "n0",
+            "n1",
+            Payload::Replicate {
+                state: n0.state.clone(),
+                seq: n0.next_seq,
+                epoch: n0.epoch,
+            },
+        );
+        let full = serde_json::to_string(&full).unwrap();
+        assert!(
+            delta.len() * 20 < full.len(),
+            "delta of {} bytes against a full state of {}",
+            delta.len(),
+            full.len()
+        );
+    }
+}
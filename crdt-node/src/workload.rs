@@ -0,0 +1,51 @@
+use crdt::{Crdt, GCounter, GSet, PnCounter};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// A CRDT that serves one of Maelstrom's CRDT workloads: `add` requests
+/// update it, `read` requests return its value.
+pub trait Workload: Crdt + PartialEq {
+    /// Fields of an `add` request.
+    type Add: Serialize + DeserializeOwned;
+
+    /// Applies `add` and returns the delta to replicate.
+    fn add(&mut self, replica: &str, add: Self::Add) -> Self;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddElement {
+    element: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddCount {
+    delta: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddDelta {
+    delta: i64,
+}
+
+impl Workload for GSet<i64> {
+    type Add = AddElement;
+
+    fn add(&mut self, _replica: &str, add: AddElement) -> Self {
+        self.insert(add.element)
+    }
+}
+
+impl Workload for GCounter {
+    type Add = AddCount;
+
+    fn add(&mut self, replica: &str, add: AddCount) -> Self {
+        self.increment(replica, add.delta)
+    }
+}
+
+impl Workload for PnCounter {
+    type Add = AddDelta;
+
+    fn add(&mut self, replica: &str, add: AddDelta) -> Self {
+        PnCounter::add(self, replica, add.delta)
+    }
+}
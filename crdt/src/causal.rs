@@ -0,0 +1,353 @@
+//! Causality tracking shared by the CRDTs that need to tell concurrent
+//! updates from superseded ones.
+//!
+//! Every update is named by a [`Dot`]. A [`VersionVector`] summarizes a
+//! gap-free history per replica, and a [`CausalContext`] is any set of
+//! dots: a version vector plus the dots seen beyond it, which a delta
+//! usually carries on its own.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A single update: the replica that made it and how many updates that
+/// replica had made, counting this one. Serializes as `[replica, counter]`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "(String, u64)", into = "(String, u64)")]
+pub struct Dot {
+    pub replica: String,
+    pub counter: u64,
+}
+
+impl Dot {
+    pub fn new(replica: &str, counter: u64) -> Self {
+        Dot {
+            replica: replica.to_string(),
+            counter,
+        }
+    }
+}
+
+impl From<(String, u64)> for Dot {
+    fn from((replica, counter): (String, u64)) -> Self {
+        Dot { replica, counter }
+    }
+}
+
+impl From<Dot> for (String, u64) {
+    fn from(dot: Dot) -> Self {
+        (dot.replica, dot.counter)
+    }
+}
+
+/// Number of updates seen from each replica, covering every dot from 1 up
+/// to that number. Replicas with none are left out, so equal histories
+/// always compare and serialize equal. Serializes as `{replica: counter}`.
+///
+/// Ordered by causality: `a < b` when `b` has seen everything `a` has and
+/// more, and incomparable when each has seen something the other has not.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "BTreeMap<String, u64>", into = "BTreeMap<String, u64>")]
+pub struct VersionVector(BTreeMap<String, u64>);
+
+impl VersionVector {
+    pub fn new() -> Self {
+        VersionVector::default()
+    }
+
+    pub fn get(&self, replica: &str) -> u64 {
+        self.0.get(replica).copied().unwrap_or_default()
+    }
+
+    /// Records the next update of `replica` and returns its dot.
+    pub fn increment(&mut self, replica: &str) -> Dot {
+        let counter = self.0.entry(replica.to_string()).or_default();
+        *counter += 1;
+        Dot::new(replica, *counter)
+    }
+
+    pub fn contains(&self, dot: &Dot) -> bool {
+        dot.counter <= self.get(&dot.replica)
+    }
+
+    /// `self` has seen everything `other` has.
+    pub fn dominates(&self, other: &VersionVector) -> bool {
+        other
+            .0
+            .iter()
+            .all(|(replica, counter)| self.get(replica) >= *counter)
+    }
+
+    /// Takes the entry-wise maximum with `other`.
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (replica, counter) in &other.0 {
+            let ours = self.0.entry(replica.clone()).or_default();
+            *ours = (*ours).max(*counter);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.0
+            .iter()
+            .map(|(replica, counter)| (replica.as_str(), *counter))
+    }
+}
+
+impl From<BTreeMap<String, u64>> for VersionVector {
+    /// Drops the zero counters a sender may have left in.
+    fn from(mut counters: BTreeMap<String, u64>) -> Self {
+        counters.retain(|_, counter| *counter > 0);
+        VersionVector(counters)
+    }
+}
+
+impl From<VersionVector> for BTreeMap<String, u64> {
+    fn from(vv: VersionVector) -> Self {
+        vv.0
+    }
+}
+
+impl PartialOrd for VersionVector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.dominates(other), other.dominates(self)) {
+            (true, true) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Greater),
+            (false, true) => Some(Ordering::Less),
+            (false, false) => None,
+        }
+    }
+}
+
+/// A set of dots, stored as a version vector for the gap-free prefix of each
+/// replica's history plus the dots seen beyond a gap.
+///
+/// Dots that close a gap are folded into the version vector as they arrive,
+/// so a context that has seen every dot is just a version vector, and equal
+/// sets of dots always have the same representation. Serializes as
+/// `{"vv": {replica: counter}, "dots": [[replica, counter], ...]}`, leaving
+/// out `dots` when there are none.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "RawContext", into = "RawContext")]
+pub struct CausalContext {
+    vv: VersionVector,
+    dots: BTreeSet<Dot>,
+}
+
+impl CausalContext {
+    pub fn new() -> Self {
+        CausalContext::default()
+    }
+
+    pub fn contains(&self, dot: &Dot) -> bool {
+        self.vv.contains(dot) || self.dots.contains(dot)
+    }
+
+    /// The dot for the next update of `replica`, recorded as seen.
+    pub fn next_dot(&mut self, replica: &str) -> Dot {
+        let latest = self
+            .dots
+            .iter()
+            .filter(|dot| dot.replica == replica)
+            .map(|dot| dot.counter)
+            .max()
+            .unwrap_or_default()
+            .max(self.vv.get(replica));
+
+        let dot = Dot::new(replica, latest + 1);
+        self.insert(dot.clone());
+        dot
+    }
+
+    pub fn insert(&mut self, dot: Dot) {
+        if !self.vv.contains(&dot) {
+            self.dots.insert(dot);
+            self.compact();
+        }
+    }
+
+    pub fn merge(&mut self, other: &CausalContext) {
+        self.vv.merge(&other.vv);
+        self.dots.extend(other.dots.iter().cloned());
+        self.compact();
+    }
+
+    /// The gap-free part of this context.
+    pub fn version_vector(&self) -> &VersionVector {
+        &self.vv
+    }
+
+    /// Folds dots that extend the version vector into it and drops the ones
+    /// it already covers.
+    fn compact(&mut self) {
+        let dots = std::mem::take(&mut self.dots);
+        // dots are ordered by replica then counter, so each replica's run
+        // is visited from its lowest counter up
+        for dot in dots {
+            if dot.counter == self.vv.get(&dot.replica) + 1 {
+                self.vv.increment(&dot.replica);
+            } else if !self.vv.contains(&dot) {
+                self.dots.insert(dot);
+            }
+        }
+    }
+}
+
+impl From<VersionVector> for CausalContext {
+    fn from(vv: VersionVector) -> Self {
+        CausalContext {
+            vv,
+            dots: BTreeSet::new(),
+        }
+    }
+}
+
+impl FromIterator<Dot> for CausalContext {
+    fn from_iter<I: IntoIterator<Item = Dot>>(dots: I) -> Self {
+        let mut context = CausalContext::new();
+        context.dots.extend(dots);
+        context.compact();
+        context
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawContext {
+    vv: VersionVector,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    dots: BTreeSet<Dot>,
+}
+
+impl From<RawContext> for CausalContext {
+    fn from(raw: RawContext) -> Self {
+        // compact in case the sender did not
+        let mut context = CausalContext {
+            vv: raw.vv,
+            dots: raw.dots,
+        };
+        context.compact();
+        context
+    }
+}
+
+impl From<CausalContext> for RawContext {
+    fn from(context: CausalContext) -> Self {
+        RawContext {
+            vv: context.vv,
+            dots: context.dots,
+        }
+    }
+}
+
+/// Joins two dot stores that map each dot to one entry: an entry survives if
+/// both sides have it, or if the side without it has never seen its dot.
+/// Entries whose dot the other side has seen but dropped were superseded.
+pub(crate) fn join<T: Clone>(
+    ours: &[(T, Dot)],
+    our_context: &CausalContext,
+    theirs: &[(T, Dot)],
+    their_context: &CausalContext,
+) -> Vec<(T, Dot)> {
+    let their_dots: BTreeSet<&Dot> = theirs.iter().map(|(_, dot)| dot).collect();
+    let our_dots: BTreeSet<&Dot> = ours.iter().map(|(_, dot)| dot).collect();
+
+    let mut joined: Vec<(T, Dot)> = ours
+        .iter()
+        .filter(|(_, dot)| their_dots.contains(dot) || !their_context.contains(dot))
+        .cloned()
+        .collect();
+    joined.extend(
+        theirs
+            .iter()
+            .filter(|(_, dot)| !our_dots.contains(dot) && !our_context.contains(dot))
+            .cloned(),
+    );
+
+    // keep states that saw the same updates equal, whatever the merge order
+    joined.sort_by(|(_, a), (_, b)| a.cmp(b));
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn context_is_the_same_whatever_order_dots_arrive_in() {
+        let dots = [
+            Dot::new("a", 3),
+            Dot::new("a", 1),
+            Dot::new("b", 2),
+            Dot::new("a", 2),
+        ];
+
+        let mut forwards = CausalContext::new();
+        let mut backwards = CausalContext::new();
+        for dot in &dots {
+            forwards.insert(dot.clone());
+        }
+        for dot in dots.iter().rev() {
+            backwards.insert(dot.clone());
+        }
+
+        assert_eq!(forwards, backwards);
+        assert_eq!(
+            serde_json::to_value(&forwards).unwrap(),
+            json!({"vv": {"a": 3}, "dots": [["b", 2]]})
+        );
+    }
+
+    #[test]
+    fn closing_every_gap_leaves_a_version_vector() {
+        let mut context: CausalContext = [Dot::new("a", 2)].into_iter().collect();
+        context.insert(Dot::new("a", 1));
+
+        assert_eq!(
+            serde_json::to_value(&context).unwrap(),
+            json!({"vv": {"a": 2}})
+        );
+        assert_eq!(context.next_dot("a"), Dot::new("a", 3));
+    }
+
+    #[test]
+    fn version_vectors_are_ordered_by_causality() {
+        let mut a = VersionVector::new();
+        a.increment("a");
+        let mut b = a.clone();
+        b.increment("b");
+        let mut c = a.clone();
+        c.increment("c");
+
+        assert!(a < b);
+        assert_eq!(b.partial_cmp(&c), None);
+
+        b.merge(&c);
+        assert!(c < b);
+    }
+
+    #[test]
+    fn zero_counters_from_the_wire_are_dropped() {
+        let vv: VersionVector = serde_json::from_value(json!({"a": 0, "b": 1})).unwrap();
+
+        assert_eq!(vv, serde_json::from_value(json!({"b": 1})).unwrap());
+        assert_eq!(serde_json::to_value(&vv).unwrap(), json!({"b": 1}));
+        let empty: VersionVector = serde_json::from_value(json!({"a": 0})).unwrap();
+        assert_eq!(empty, VersionVector::new());
+    }
+
+    #[test]
+    fn uncompacted_contexts_from_the_wire_are_compacted() {
+        let context: CausalContext =
+            serde_json::from_value(json!({"vv": {"a": 1, "b": 0}, "dots": [["a", 2], ["a", 1]]}))
+                .unwrap();
+
+        assert_eq!(
+            context,
+            VersionVector(BTreeMap::from([("a".to_string(), 2)])).into()
+        );
+    }
+}
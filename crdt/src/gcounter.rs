@@ -0,0 +1,55 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Crdt;
+
+/// Grow-only counter: one count per replica, merged by taking the maximum.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GCounter {
+    counts: BTreeMap<String, u64>,
+}
+
+impl GCounter {
+    pub fn increment(&mut self, replica: &str, delta: u64) -> GCounter {
+        let count = self.counts.entry(replica.to_string()).or_default();
+        *count += delta;
+
+        GCounter {
+            counts: BTreeMap::from([(replica.to_string(), *count)]),
+        }
+    }
+
+    /// What `replica` has added so far.
+    pub fn count(&self, replica: &str) -> u64 {
+        self.counts.get(replica).copied().unwrap_or_default()
+    }
+}
+
+impl Crdt for GCounter {
+    type Value = u64;
+
+    fn merge(&mut self, other: &Self) {
+        for (replica, count) in &other.counts {
+            let ours = self.counts.entry(replica.clone()).or_default();
+            *ours = (*ours).max(*count);
+        }
+    }
+
+    fn value(&self) -> u64 {
+        self.counts.values().sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_laws;
+
+    #[test]
+    fn merge_laws() {
+        check_laws(|rng, replica, counter: &mut GCounter| {
+            counter.increment(replica, rng.below(10))
+        });
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::BTreeSet;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::Crdt;
+
+/// Grow-only set, merged by union.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "T: Ord + Serialize + DeserializeOwned")]
+pub struct GSet<T: Ord> {
+    elements: BTreeSet<T>,
+}
+
+impl<T: Ord> Default for GSet<T> {
+    fn default() -> Self {
+        GSet {
+            elements: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Clone + Ord> GSet<T> {
+    pub fn insert(&mut self, element: T) -> GSet<T> {
+        self.elements.insert(element.clone());
+
+        GSet {
+            elements: BTreeSet::from([element]),
+        }
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.elements.contains(element)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements.iter()
+    }
+}
+
+impl<T> Crdt for GSet<T>
+where
+    T: Clone + Ord + Serialize + DeserializeOwned,
+{
+    type Value = BTreeSet<T>;
+
+    fn merge(&mut self, other: &Self) {
+        self.elements.extend(other.elements.iter().cloned());
+    }
+
+    fn value(&self) -> BTreeSet<T> {
+        self.elements.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_laws;
+
+    #[test]
+    fn merge_laws() {
+        check_laws(|rng, _, set: &mut GSet<u64>| set.insert(rng.below(8)));
+    }
+}
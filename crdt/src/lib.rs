@@ -0,0 +1,150 @@
+//! State-based CRDTs (Shapiro et al., "Conflict-free Replicated Data Types").
+//!
+//! Replicas update their own copy and exchange whole states; `merge` is
+//! commutative, associative and idempotent, so replicas that have seen the
+//! same updates end up equal however often and in whatever order states
+//! were exchanged. Replicas are identified by their Maelstrom node id.
+//!
+//! Mutators are delta-mutators (Almeida et al., "Delta State Replicated Data
+//! Types"): besides updating the state they return a delta, a small state
+//! that has the same effect as the update when merged into any replica.
+//! Shipping deltas instead of whole states keeps replication messages small.
+//!
+//! Types that must tell concurrent updates from superseded ones track
+//! causality with the dots and version vectors of [`causal`], which other
+//! nodes can use on their own.
+
+pub mod causal;
+mod gcounter;
+mod gset;
+mod lww;
+mod mvregister;
+mod orset;
+mod pncounter;
+mod twopset;
+
+pub use causal::{CausalContext, Dot, VersionVector};
+pub use gcounter::GCounter;
+pub use gset::GSet;
+pub use lww::LwwRegister;
+pub use mvregister::MvRegister;
+pub use orset::OrSet;
+pub use pncounter::PnCounter;
+pub use twopset::TwoPSet;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+pub trait Crdt: Clone + Default + Serialize + DeserializeOwned {
+    /// What clients read.
+    type Value;
+
+    /// Folds `other` into this state.
+    fn merge(&mut self, other: &Self);
+
+    fn value(&self) -> Self::Value;
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("CRDT state is always serializable")
+    }
+
+    fn from_json(value: serde_json::Value) -> serde_json::Result<Self> {
+        serde_json::from_value(value)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fmt::Debug;
+
+    use super::Crdt;
+
+    const REPLICAS: [&str; 3] = ["n0", "n1", "n2"];
+
+    /// xorshift64, so failures replay from their seed.
+    pub(crate) struct Rng(u64);
+
+    impl Rng {
+        fn new(seed: u64) -> Self {
+            Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+        }
+
+        pub(crate) fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+    }
+
+    fn merged<C: Crdt>(a: &C, b: &C) -> C {
+        let mut merged = a.clone();
+        merged.merge(b);
+        merged
+    }
+
+    /// Three replicas after a random run of `update`s and state exchanges,
+    /// so they share some history and have diverged on the rest. Every
+    /// delta `update` returns must have the same effect as the update.
+    fn replicas<C: Crdt + PartialEq + Debug>(
+        rng: &mut Rng,
+        update: &mut impl FnMut(&mut Rng, &str, &mut C) -> C,
+    ) -> [C; 3] {
+        let mut replicas = [C::default(), C::default(), C::default()];
+        for _ in 0..rng.below(30) {
+            let i = rng.below(3) as usize;
+            if rng.below(4) == 0 {
+                let other = replicas[rng.below(3) as usize].clone();
+                replicas[i].merge(&other);
+            } else {
+                let before = replicas[i].clone();
+                let delta = update(rng, REPLICAS[i], &mut replicas[i]);
+                assert_eq!(
+                    merged(&before, &delta),
+                    replicas[i],
+                    "delta of {:?}",
+                    before
+                );
+            }
+        }
+        replicas
+    }
+
+    /// Checks that merge is commutative, associative and idempotent, that
+    /// deltas match their updates, and that states survive a JSON
+    /// round-trip, over seeded random histories.
+    pub(crate) fn check_laws<C>(mut update: impl FnMut(&mut Rng, &str, &mut C) -> C)
+    where
+        C: Crdt + PartialEq + Debug,
+    {
+        for seed in 1..=500 {
+            let mut rng = Rng::new(seed);
+            let [a, b, c] = replicas(&mut rng, &mut update);
+
+            assert_eq!(
+                merged(&a, &b),
+                merged(&b, &a),
+                "commutativity, seed {}",
+                seed
+            );
+            assert_eq!(
+                merged(&merged(&a, &b), &c),
+                merged(&a, &merged(&b, &c)),
+                "associativity, seed {}",
+                seed
+            );
+            assert_eq!(merged(&a, &a), a, "idempotence, seed {}", seed);
+            assert_eq!(
+                merged(&merged(&a, &b), &b),
+                merged(&a, &b),
+                "idempotence, seed {}",
+                seed
+            );
+            assert_eq!(
+                C::from_json(a.to_json()).unwrap(),
+                a,
+                "round-trip, seed {}",
+                seed
+            );
+        }
+    }
+}
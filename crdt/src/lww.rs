@@ -0,0 +1,78 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::Crdt;
+
+/// Last-writer-wins register. Each write carries a caller-supplied
+/// timestamp, e.g. wall-clock milliseconds or a Lamport clock; ties are
+/// broken by replica id so every replica picks the same winner. A replica
+/// must not reuse a timestamp for a different value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "T: Serialize + DeserializeOwned")]
+pub struct LwwRegister<T> {
+    value: Option<T>,
+    timestamp: u64,
+    replica: String,
+}
+
+impl<T> Default for LwwRegister<T> {
+    fn default() -> Self {
+        LwwRegister {
+            value: None,
+            timestamp: 0,
+            replica: String::new(),
+        }
+    }
+}
+
+impl<T: Clone> LwwRegister<T> {
+    /// Sets the register unless it already holds a later write. The delta is
+    /// the whole register, which is no bigger than the write itself.
+    pub fn set(&mut self, replica: &str, timestamp: u64, value: T) -> LwwRegister<T> {
+        self.assign(replica, timestamp, value);
+        self.clone()
+    }
+
+    fn assign(&mut self, replica: &str, timestamp: u64, value: T) {
+        if (timestamp, replica) > (self.timestamp, self.replica.as_str()) {
+            self.value = Some(value);
+            self.timestamp = timestamp;
+            self.replica = replica.to_string();
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+impl<T> Crdt for LwwRegister<T>
+where
+    T: Clone + Serialize + DeserializeOwned,
+{
+    type Value = Option<T>;
+
+    fn merge(&mut self, other: &Self) {
+        if let Some(value) = &other.value {
+            self.assign(&other.replica, other.timestamp, value.clone());
+        }
+    }
+
+    fn value(&self) -> Option<T> {
+        self.value.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_laws;
+
+    #[test]
+    fn merge_laws() {
+        check_laws(|rng, replica, register: &mut LwwRegister<String>| {
+            // one value per replica and timestamp, as the type requires
+            let timestamp = rng.below(10);
+            register.set(replica, timestamp, format!("{}@{}", replica, timestamp))
+        });
+    }
+}
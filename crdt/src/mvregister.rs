@@ -0,0 +1,91 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    causal::{self, CausalContext, Dot},
+    Crdt,
+};
+
+/// Multi-value register. A write supersedes every value its replica has
+/// seen; concurrent writes are all kept, and a read returns each of them
+/// for the client to reconcile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "T: Serialize + DeserializeOwned")]
+pub struct MvRegister<T> {
+    /// Current values with the dot of the write that made each, ordered by
+    /// dot.
+    values: Vec<(T, Dot)>,
+    /// Every write this replica has seen.
+    context: CausalContext,
+}
+
+impl<T> Default for MvRegister<T> {
+    fn default() -> Self {
+        MvRegister {
+            values: Vec::new(),
+            context: CausalContext::new(),
+        }
+    }
+}
+
+impl<T: Clone> MvRegister<T> {
+    /// Sets the register. The delta holds the new value and the dots of the
+    /// values it supersedes.
+    pub fn set(&mut self, replica: &str, value: T) -> MvRegister<T> {
+        let mut superseded: CausalContext =
+            self.values.iter().map(|(_, dot)| dot.clone()).collect();
+
+        let dot = self.context.next_dot(replica);
+        superseded.insert(dot.clone());
+        self.values = vec![(value.clone(), dot.clone())];
+
+        MvRegister {
+            values: vec![(value, dot)],
+            context: superseded,
+        }
+    }
+}
+
+impl<T> Crdt for MvRegister<T>
+where
+    T: Clone + PartialEq + Serialize + DeserializeOwned,
+{
+    type Value = Vec<T>;
+
+    fn merge(&mut self, other: &Self) {
+        self.values = causal::join(&self.values, &self.context, &other.values, &other.context);
+        self.context.merge(&other.context);
+    }
+
+    fn value(&self) -> Vec<T> {
+        self.values.iter().map(|(value, _)| value.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_laws;
+
+    #[test]
+    fn merge_laws() {
+        check_laws(|rng, replica, register: &mut MvRegister<u64>| {
+            register.set(replica, rng.below(8))
+        });
+    }
+
+    #[test]
+    fn keeps_concurrent_writes_until_one_supersedes_them() {
+        let mut a = MvRegister::default();
+        let mut b = MvRegister::default();
+        a.set("a", 1);
+        b.set("b", 2);
+
+        a.merge(&b);
+        assert_eq!(a.value(), vec![1, 2]);
+
+        b.merge(&a);
+        b.set("b", 3);
+        a.merge(&b);
+        assert_eq!(a.value(), vec![3]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeSet;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    causal::{self, CausalContext, Dot},
+    Crdt,
+};
+
+/// Observed-remove set. Every insert is tagged with a fresh dot and a remove
+/// only retires the dots the removing replica has seen, so an insert
+/// concurrent with a remove survives it ("add wins").
+///
+/// Removed inserts leave no tombstone: their dots stay in the causal context,
+/// which compacts to a version vector once every dot has arrived.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "T: Serialize + DeserializeOwned")]
+pub struct OrSet<T> {
+    /// Live inserts, ordered by dot.
+    entries: Vec<(T, Dot)>,
+    /// Every dot this replica has seen, live or removed.
+    context: CausalContext,
+}
+
+impl<T> Default for OrSet<T> {
+    fn default() -> Self {
+        OrSet {
+            entries: Vec::new(),
+            context: CausalContext::new(),
+        }
+    }
+}
+
+impl<T: Clone + Ord> OrSet<T> {
+    /// Inserts `element`, superseding the inserts of it this replica has
+    /// seen.
+    pub fn insert(&mut self, replica: &str, element: T) -> OrSet<T> {
+        let mut delta = self.remove(&element);
+
+        let dot = self.context.next_dot(replica);
+        let idx = self.entries.partition_point(|(_, other)| *other < dot);
+        self.entries.insert(idx, (element.clone(), dot.clone()));
+        delta.entries.push((element, dot.clone()));
+        delta.context.insert(dot);
+
+        delta
+    }
+
+    /// Removes every insert of `element` this replica has seen.
+    pub fn remove(&mut self, element: &T) -> OrSet<T> {
+        let dots: CausalContext = self
+            .entries
+            .iter()
+            .filter(|(inserted, _)| inserted == element)
+            .map(|(_, dot)| dot.clone())
+            .collect();
+        self.entries.retain(|(inserted, _)| inserted != element);
+
+        OrSet {
+            entries: Vec::new(),
+            context: dots,
+        }
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.entries.iter().any(|(inserted, _)| inserted == element)
+    }
+}
+
+impl<T> Crdt for OrSet<T>
+where
+    T: Clone + Ord + Serialize + DeserializeOwned,
+{
+    type Value = BTreeSet<T>;
+
+    fn merge(&mut self, other: &Self) {
+        self.entries = causal::join(&self.entries, &self.context, &other.entries, &other.context);
+        self.context.merge(&other.context);
+    }
+
+    fn value(&self) -> BTreeSet<T> {
+        self.entries
+            .iter()
+            .map(|(element, _)| element.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_laws;
+
+    #[test]
+    fn merge_laws() {
+        check_laws(|rng, replica, set: &mut OrSet<u64>| {
+            let element = rng.below(4);
+            if rng.below(3) == 0 {
+                set.remove(&element)
+            } else {
+                set.insert(replica, element)
+            }
+        });
+    }
+
+    #[test]
+    fn concurrent_insert_survives_a_remove() {
+        let mut a = OrSet::default();
+        a.insert("a", 1);
+        let mut b = a.clone();
+
+        a.remove(&1);
+        b.insert("b", 1);
+        a.merge(&b);
+        b.merge(&a);
+
+        assert!(a.contains(&1));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn remove_leaves_no_tombstone() {
+        let mut set = OrSet::default();
+        set.insert("a", 1);
+        set.insert("a", 2);
+        set.remove(&1);
+
+        assert_eq!(
+            set.to_json(),
+            serde_json::json!({"entries": [[2, ["a", 2]]], "context": {"vv": {"a": 2}}})
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Crdt, GCounter};
+
+/// Counter that can also go down, kept as a pair of grow-only counters for
+/// increments and decrements.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PnCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PnCounter {
+    pub fn add(&mut self, replica: &str, delta: i64) -> PnCounter {
+        if delta >= 0 {
+            PnCounter {
+                increments: self.increments.increment(replica, delta as u64),
+                decrements: GCounter::default(),
+            }
+        } else {
+            PnCounter {
+                increments: GCounter::default(),
+                decrements: self.decrements.increment(replica, delta.unsigned_abs()),
+            }
+        }
+    }
+}
+
+impl Crdt for PnCounter {
+    type Value = i64;
+
+    fn merge(&mut self, other: &Self) {
+        self.increments.merge(&other.increments);
+        self.decrements.merge(&other.decrements);
+    }
+
+    fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_laws;
+
+    #[test]
+    fn merge_laws() {
+        check_laws(|rng, replica, counter: &mut PnCounter| {
+            counter.add(replica, rng.below(21) as i64 - 10)
+        });
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::BTreeSet;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Crdt, GSet};
+
+/// Two-phase set: a grow-only set of additions and one of removals. Once
+/// removed, an element can never be added back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound = "T: Ord + Serialize + DeserializeOwned")]
+pub struct TwoPSet<T: Ord> {
+    added: GSet<T>,
+    removed: GSet<T>,
+}
+
+impl<T: Ord> Default for TwoPSet<T> {
+    fn default() -> Self {
+        TwoPSet {
+            added: GSet::default(),
+            removed: GSet::default(),
+        }
+    }
+}
+
+impl<T: Clone + Ord> TwoPSet<T> {
+    pub fn insert(&mut self, element: T) -> TwoPSet<T> {
+        TwoPSet {
+            added: self.added.insert(element),
+            removed: GSet::default(),
+        }
+    }
+
+    /// Removes `element` if this replica has seen it added. Returns `None`
+    /// if it has not.
+    pub fn remove(&mut self, element: &T) -> Option<TwoPSet<T>> {
+        if !self.added.contains(element) {
+            return None;
+        }
+
+        Some(TwoPSet {
+            added: GSet::default(),
+            removed: self.removed.insert(element.clone()),
+        })
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.added.contains(element) && !self.removed.contains(element)
+    }
+}
+
+impl<T> Crdt for TwoPSet<T>
+where
+    T: Clone + Ord + Serialize + DeserializeOwned,
+{
+    type Value = BTreeSet<T>;
+
+    fn merge(&mut self, other: &Self) {
+        self.added.merge(&other.added);
+        self.removed.merge(&other.removed);
+    }
+
+    fn value(&self) -> BTreeSet<T> {
+        self.added
+            .iter()
+            .filter(|element| !self.removed.contains(element))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::check_laws;
+
+    #[test]
+    fn merge_laws() {
+        check_laws(|rng, _, set: &mut TwoPSet<u64>| {
+            let element = rng.below(8);
+            if rng.below(3) == 0 {
+                // nothing to remove without having seen the insert
+                set.remove(&element).unwrap_or_default()
+            } else {
+                set.insert(element)
+            }
+        });
+    }
+}
@@ -0,0 +1,76 @@
+mod node;
+
+use std::{
+    io::{stdin, BufRead, Write},
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use node::CounterNode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// How often the main loop wakes up to retry RPCs that went unanswered.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Body<Payload> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    msg_id: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    in_reply_to: Option<u64>,
+
+    #[serde(flatten)]
+    payload: Payload,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Message<Payload> {
+    pub src: String,
+    pub dest: String,
+    pub body: Body<Payload>,
+}
+
+fn read_from_stdin<Payload: DeserializeOwned>(tx: Sender<Message<Payload>>) {
+    for line in stdin().lock().lines() {
+        let message = serde_json::from_str::<Message<Payload>>(&line.unwrap()).unwrap();
+        tx.send(message).unwrap();
+    }
+}
+
+fn write_to_stdout<Payload: Serialize>(message: Message<Payload>) {
+    let mut stdout = std::io::stdout();
+    let output = serde_json::to_string(&message).unwrap();
+    writeln!(stdout, "{}", output).unwrap();
+    stdout.flush().unwrap();
+}
+
+fn main() {
+    let mut node = CounterNode::default();
+
+    let (reader_tx, reader_rx) = mpsc::channel();
+    thread::spawn(move || read_from_stdin(reader_tx));
+
+    let mut last_tick = Instant::now();
+    loop {
+        let timeout = TICK_INTERVAL.saturating_sub(last_tick.elapsed());
+
+        match reader_rx.recv_timeout(timeout) {
+            Ok(message) => {
+                for to_send in node.step(message) {
+                    write_to_stdout(to_send);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        if last_tick.elapsed() >= TICK_INTERVAL {
+            for to_send in node.on_tick() {
+                write_to_stdout(to_send);
+            }
+            last_tick = Instant::now();
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    collections::HashMap,
+    mem,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Body, Message};
+
+/// Maelstrom's sequentially consistent key-value service.
+const SEQ_KV: &str = "seq-kv";
+/// RPCs to `seq-kv` that go unanswered this long are retried.
+const RPC_TIMEOUT: Duration = Duration::from_millis(1000);
+
+const KEY_DOES_NOT_EXIST: u64 = 20;
+const PRECONDITION_FAILED: u64 = 22;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Init {
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+    InitOk {},
+    Add {
+        delta: u64,
+    },
+    AddOk {},
+    /// Without a key this is a client reading the counter, with one it is a
+    /// read sent to `seq-kv`.
+    Read {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        key: Option<String>,
+    },
+    ReadOk {
+        value: u64,
+    },
+    Write {
+        key: String,
+        value: u64,
+    },
+    WriteOk {},
+    Cas {
+        key: String,
+        from: u64,
+        to: u64,
+        create_if_not_exists: bool,
+    },
+    CasOk {},
+    Error {
+        code: u64,
+        text: String,
+    },
+}
+
+/// Who to answer once a client request is done.
+#[derive(Debug, Clone)]
+struct Client {
+    src: String,
+    msg_id: Option<u64>,
+}
+
+/// What an RPC to `seq-kv` was sent for.
+#[derive(Debug)]
+enum Call {
+    /// Read of this node's own key, ahead of a CAS.
+    OwnRead,
+    /// CAS of this node's own key that carries the adds of `batch`.
+    Cas { to: u64, batch: Vec<(u64, Client)> },
+    /// Write to this node's freshness key, ahead of the reads of `read`.
+    Freshness { read: u64 },
+    /// Read of one node's key on behalf of `read`.
+    KeyRead { read: u64 },
+}
+
+#[derive(Debug)]
+struct Rpc {
+    call: Call,
+    payload: Payload,
+    sent_at: Instant,
+}
+
+#[derive(Debug)]
+struct PendingRead {
+    client: Client,
+    remaining: usize,
+    sum: u64,
+}
+
+/// Grow-only counter kept in `seq-kv`, one key per node.
+///
+/// Only this node ever writes its own key, so adds are batched into a single
+/// CAS of that key at a time and a conflict just means our cached value is
+/// stale. An add is acknowledged once the CAS carrying it succeeds.
+///
+/// `seq-kv` may serve a node stale values for keys written by others. A read
+/// therefore first writes a unique value to a key of this node: that write
+/// has to be ordered after everything already in the store, and so do the
+/// reads this node issues after it.
+#[derive(Debug, Default)]
+pub struct CounterNode {
+    node_id: String,
+    node_ids: Vec<String>,
+    next_msg_id: u64,
+    /// Value of our own key as of our last read or successful CAS.
+    own: Option<u64>,
+    /// Adds not yet carried by a CAS.
+    queued: Vec<(u64, Client)>,
+    /// A CAS that failed its precondition. An earlier copy of the very same
+    /// CAS, retried after a timeout, may have been applied; the next read of
+    /// our own key tells.
+    unconfirmed: Option<(u64, Vec<(u64, Client)>)>,
+    rpcs: HashMap<u64, Rpc>,
+    reads: HashMap<u64, PendingRead>,
+    next_read_id: u64,
+}
+
+impl CounterNode {
+    pub fn step(&mut self, input: Message<Payload>) -> Vec<Message<Payload>> {
+        let client = Client {
+            src: input.src.clone(),
+            msg_id: input.body.msg_id,
+        };
+
+        match input.body.payload {
+            Payload::Init { node_id, node_ids } => {
+                self.node_id = node_id;
+                self.node_ids = node_ids;
+                vec![self.reply(client, Payload::InitOk {})]
+            }
+            Payload::Add { delta } => {
+                self.queued.push((delta, client));
+                self.flush()
+            }
+            Payload::Read { key: None } => self.start_read(client),
+            Payload::ReadOk { .. }
+            | Payload::WriteOk {}
+            | Payload::CasOk {}
+            | Payload::Error { .. } => {
+                let rpc = input
+                    .body
+                    .in_reply_to
+                    .and_then(|in_reply_to| self.rpcs.remove(&in_reply_to));
+                match rpc {
+                    Some(rpc) => self.on_reply(rpc.call, rpc.payload, input.body.payload),
+                    // a late reply to an RPC that was already retried
+                    None => vec![],
+                }
+            }
+            _ => panic!("invalid type"),
+        }
+    }
+
+    /// Retries RPCs that went unanswered.
+    pub fn on_tick(&mut self) -> Vec<Message<Payload>> {
+        let expired: Vec<u64> = self
+            .rpcs
+            .iter()
+            .filter(|(_, rpc)| rpc.sent_at.elapsed() >= RPC_TIMEOUT)
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+
+        // a CAS is resent unchanged, so at most one of its copies applies
+        let mut to_send = Vec::new();
+        for msg_id in expired {
+            if let Some(rpc) = self.rpcs.remove(&msg_id) {
+                to_send.push(self.call(rpc.call, rpc.payload));
+            }
+        }
+
+        to_send
+    }
+
+    fn msg_id(&mut self) -> u64 {
+        self.next_msg_id += 1;
+        self.next_msg_id
+    }
+
+    fn key(node: &str) -> String {
+        format!("counter-{}", node)
+    }
+
+    fn reply(&self, client: Client, payload: Payload) -> Message<Payload> {
+        Message {
+            src: self.node_id.clone(),
+            dest: client.src,
+            body: Body {
+                msg_id: client.msg_id,
+                in_reply_to: client.msg_id,
+                payload,
+            },
+        }
+    }
+
+    fn call(&mut self, call: Call, payload: Payload) -> Message<Payload> {
+        let msg_id = self.msg_id();
+        self.rpcs.insert(
+            msg_id,
+            Rpc {
+                call,
+                payload: payload.clone(),
+                sent_at: Instant::now(),
+            },
+        );
+
+        Message {
+            src: self.node_id.clone(),
+            dest: SEQ_KV.to_string(),
+            body: Body {
+                msg_id: Some(msg_id),
+                in_reply_to: None,
+                payload,
+            },
+        }
+    }
+
+    fn writing_own_key(&self) -> bool {
+        self.rpcs
+            .values()
+            .any(|rpc| matches!(rpc.call, Call::OwnRead | Call::Cas { .. }))
+    }
+
+    /// Starts the next CAS of our own key if none is in flight, reading the
+    /// key first when its value is not known.
+    fn flush(&mut self) -> Vec<Message<Payload>> {
+        if self.writing_own_key() {
+            return vec![];
+        }
+
+        let key = CounterNode::key(&self.node_id);
+        let Some(own) = self.own else {
+            return vec![self.call(Call::OwnRead, Payload::Read { key: Some(key) })];
+        };
+        if self.queued.is_empty() {
+            return vec![];
+        }
+
+        let batch = mem::take(&mut self.queued);
+        let to = own + batch.iter().map(|(delta, _)| delta).sum::<u64>();
+        let payload = Payload::Cas {
+            key,
+            from: own,
+            to,
+            create_if_not_exists: true,
+        };
+
+        vec![self.call(Call::Cas { to, batch }, payload)]
+    }
+
+    fn start_read(&mut self, client: Client) -> Vec<Message<Payload>> {
+        self.next_read_id += 1;
+        let read = self.next_read_id;
+        self.reads.insert(
+            read,
+            PendingRead {
+                client,
+                remaining: self.node_ids.len(),
+                sum: 0,
+            },
+        );
+
+        let payload = Payload::Write {
+            key: format!("freshness-{}", self.node_id),
+            value: self.next_msg_id + 1,
+        };
+        vec![self.call(Call::Freshness { read }, payload)]
+    }
+
+    fn on_reply(&mut self, call: Call, request: Payload, reply: Payload) -> Vec<Message<Payload>> {
+        let value = match reply {
+            Payload::ReadOk { value } => Some(value),
+            Payload::Error {
+                code: KEY_DOES_NOT_EXIST,
+                ..
+            } => Some(0),
+            _ => None,
+        };
+
+        match (call, value, reply) {
+            (Call::OwnRead, Some(value), _) => {
+                self.own = Some(value);
+
+                let mut to_send = Vec::new();
+                if let Some((to, batch)) = self.unconfirmed.take() {
+                    if value >= to {
+                        // an earlier copy of the CAS did go through
+                        for (_, client) in batch {
+                            to_send.push(self.reply(client, Payload::AddOk {}));
+                        }
+                    } else {
+                        self.queued.splice(0..0, batch);
+                    }
+                }
+                to_send.extend(self.flush());
+                to_send
+            }
+            (Call::Cas { to, batch }, _, Payload::CasOk {}) => {
+                self.own = Some(to);
+
+                let mut to_send: Vec<Message<Payload>> = batch
+                    .into_iter()
+                    .map(|(_, client)| self.reply(client, Payload::AddOk {}))
+                    .collect();
+                to_send.extend(self.flush());
+                to_send
+            }
+            (
+                Call::Cas { to, batch },
+                _,
+                Payload::Error {
+                    code: PRECONDITION_FAILED,
+                    ..
+                },
+            ) => {
+                self.own = None;
+                self.unconfirmed = Some((to, batch));
+                self.flush()
+            }
+            (Call::Freshness { read }, _, Payload::WriteOk {}) => {
+                let nodes = self.node_ids.clone();
+                nodes
+                    .iter()
+                    .map(|node| {
+                        let key = CounterNode::key(node);
+                        self.call(Call::KeyRead { read }, Payload::Read { key: Some(key) })
+                    })
+                    .collect()
+            }
+            (Call::KeyRead { read }, Some(value), _) => {
+                let Some(pending) = self.reads.get_mut(&read) else {
+                    return vec![];
+                };
+                pending.sum += value;
+                pending.remaining -= 1;
+                if pending.remaining > 0 {
+                    return vec![];
+                }
+
+                let pending = self.reads.remove(&read).unwrap();
+                vec![self.reply(pending.client, Payload::ReadOk { value: pending.sum })]
+            }
+            // anything else, e.g. the service being temporarily unavailable,
+            // is retried as is
+            (call, _, _) => vec![self.call(call, request)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    /// A `seq-kv` that answers at once; the network around it is what
+    /// misbehaves.
+    #[derive(Default)]
+    struct SeqKv(HashMap<String, u64>);
+
+    impl SeqKv {
+        fn handle(&mut self, request: Message<Payload>) -> Message<Payload> {
+            let payload = match request.body.payload {
+                Payload::Read { key: Some(key) } => match self.0.get(&key) {
+                    Some(&value) => Payload::ReadOk { value },
+                    None => Payload::Error {
+                        code: KEY_DOES_NOT_EXIST,
+                        text: "no such key".to_string(),
+                    },
+                },
+                Payload::Write { key, value } => {
+                    self.0.insert(key, value);
+                    Payload::WriteOk {}
+                }
+                Payload::Cas { key, from, to, .. } => match self.0.get(&key) {
+                    Some(&current) if current != from => Payload::Error {
+                        code: PRECONDITION_FAILED,
+                        text: "stale".to_string(),
+                    },
+                    _ => {
+                        self.0.insert(key, to);
+                        Payload::CasOk {}
+                    }
+                },
+                payload => panic!("unexpected request {:?}", payload),
+            };
+
+            Message {
+                src: SEQ_KV.to_string(),
+                dest: request.src,
+                body: Body {
+                    msg_id: None,
+                    in_reply_to: request.body.msg_id,
+                    payload,
+                },
+            }
+        }
+    }
+
+    fn request(dest: &str, msg_id: u64, payload: Payload) -> Message<Payload> {
+        Message {
+            src: format!("c{}", msg_id),
+            dest: dest.to_string(),
+            body: Body {
+                msg_id: Some(msg_id),
+                in_reply_to: None,
+                payload,
+            },
+        }
+    }
+
+    /// Runs adds through two nodes while messages to and from `seq-kv` are
+    /// dropped and duplicated, and everything is reordered. Lost requests
+    /// are retried by aging them past the timeout.
+    /// Returns the replies the clients got and the final store.
+    fn run(seed: u64, adds: &[u64]) -> (Vec<Message<Payload>>, SeqKv) {
+        let node_ids = vec!["n0".to_string(), "n1".to_string()];
+        let mut nodes: Vec<CounterNode> = node_ids
+            .iter()
+            .map(|node_id| {
+                let mut node = CounterNode::default();
+                node.step(request(
+                    node_id,
+                    0,
+                    Payload::Init {
+                        node_id: node_id.clone(),
+                        node_ids: node_ids.clone(),
+                    },
+                ));
+                node
+            })
+            .collect();
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut kv = SeqKv::default();
+        let mut network = Vec::new();
+        let mut replies = Vec::new();
+
+        for (i, delta) in adds.iter().enumerate() {
+            let node = i % nodes.len();
+            let add = request(
+                &node_ids[node],
+                i as u64 + 1,
+                Payload::Add { delta: *delta },
+            );
+            network.extend(nodes[node].step(add));
+        }
+
+        for _ in 0..100_000 {
+            if network.is_empty() {
+                if nodes.iter().all(|node| node.rpcs.is_empty()) {
+                    break;
+                }
+                // time passes: everything still unanswered is retried
+                for node in &mut nodes {
+                    for rpc in node.rpcs.values_mut() {
+                        rpc.sent_at = Instant::now() - RPC_TIMEOUT;
+                    }
+                    network.extend(node.on_tick());
+                }
+                continue;
+            }
+
+            let message: Message<Payload> = network.swap_remove(rng.gen_range(0..network.len()));
+            if message.src == SEQ_KV || message.dest == SEQ_KV {
+                match rng.gen_range(0..10) {
+                    0 => continue,
+                    1 => network.push(message.clone()),
+                    _ => {}
+                }
+            }
+
+            if message.dest == SEQ_KV {
+                network.push(kv.handle(message));
+            } else if let Some(node) = node_ids.iter().position(|id| *id == message.dest) {
+                network.extend(nodes[node].step(message));
+            } else {
+                replies.push(message);
+            }
+        }
+
+        (replies, kv)
+    }
+
+    #[test]
+    fn counts_every_add_once_despite_a_lossy_network() {
+        let adds: Vec<u64> = (1..=40).collect();
+
+        for seed in 1..=20 {
+            let (replies, kv) = run(seed, &adds);
+
+            let stored: u64 = ["n0", "n1"]
+                .iter()
+                .map(|node| kv.0.get(&CounterNode::key(node)).copied().unwrap_or(0))
+                .sum();
+            assert_eq!(stored, adds.iter().sum::<u64>(), "seed {}", seed);
+
+            let mut acked: Vec<u64> = replies
+                .iter()
+                .filter(|reply| matches!(reply.body.payload, Payload::AddOk {}))
+                .filter_map(|reply| reply.body.in_reply_to)
+                .collect();
+            acked.sort_unstable();
+            acked.dedup();
+            assert_eq!(acked.len(), adds.len(), "seed {}", seed);
+        }
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    marker::PhantomData,
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{Body, Error, Message};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Request<K, V> {
+    Read {
+        key: K,
+    },
+    Write {
+        key: K,
+        value: V,
+    },
+    Cas {
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+    },
+}
+
+/// Values stay JSON until they reach the caller, so a value of the wrong
+/// type fails that call rather than going unmatched.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Reply {
+    ReadOk { value: serde_json::Value },
+    WriteOk {},
+    CasOk {},
+    Error { code: u64, text: String },
+}
+
+#[derive(Debug, Default)]
+struct Calls {
+    next_msg_id: u64,
+    /// Where to pass the reply to each request still waiting for one, by
+    /// msg_id.
+    waiting: HashMap<u64, Sender<Reply>>,
+}
+
+/// A client of one kv service, holding values of type `V`.
+///
+/// Requests go straight to stdout and each call blocks until its reply or
+/// the timeout, so calls must not be made from the thread reading stdin:
+/// that thread hands the client every line through [`KvClient::handle`]
+/// first. Clones share their requests, so one can sit with the reader and
+/// others with whoever makes calls.
+///
+/// msg_ids are counted by the client, so a node should send the service
+/// nothing itself, nor use two clients of the same service.
+#[derive(Debug)]
+pub struct KvClient<V> {
+    node_id: String,
+    service: String,
+    timeout: Duration,
+    calls: Arc<Mutex<Calls>>,
+    value: PhantomData<fn() -> V>,
+}
+
+impl<V> Clone for KvClient<V> {
+    fn clone(&self) -> Self {
+        KvClient {
+            node_id: self.node_id.clone(),
+            service: self.service.clone(),
+            timeout: self.timeout,
+            calls: self.calls.clone(),
+            value: PhantomData,
+        }
+    }
+}
+
+impl<V> KvClient<V> {
+    /// A client sending as `node_id` to the service named `service`.
+    pub fn new(node_id: impl Into<String>, service: impl Into<String>) -> Self {
+        KvClient {
+            node_id: node_id.into(),
+            service: service.into(),
+            timeout: DEFAULT_TIMEOUT,
+            calls: Arc::default(),
+            value: PhantomData,
+        }
+    }
+
+    /// A client of the linearizable store.
+    pub fn lin_kv(node_id: impl Into<String>) -> Self {
+        Self::new(node_id, "lin-kv")
+    }
+
+    /// A client of the sequentially consistent store.
+    pub fn seq_kv(node_id: impl Into<String>) -> Self {
+        Self::new(node_id, "seq-kv")
+    }
+
+    /// A client of the last-write-wins store.
+    pub fn lww_kv(node_id: impl Into<String>) -> Self {
+        Self::new(node_id, "lww-kv")
+    }
+
+    /// How long a call waits for its reply before failing with
+    /// [`Error::Timeout`]; a second by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Takes a line read from stdin. Returns whether it was a reply from
+    /// the service, which the caller should then not handle itself; replies
+    /// to calls that have timed out are dropped here.
+    pub fn handle(&self, line: &str) -> bool {
+        let Ok(message) = serde_json::from_str::<Message<Reply>>(line) else {
+            return false;
+        };
+        if message.src != self.service {
+            return false;
+        }
+
+        let waiting = message
+            .body
+            .in_reply_to
+            .and_then(|msg_id| self.calls.lock().unwrap().waiting.remove(&msg_id));
+        if let Some(tx) = waiting {
+            // The caller may have just given up on it.
+            let _ = tx.send(message.body.payload);
+        }
+        true
+    }
+}
+
+impl<V: Serialize + DeserializeOwned> KvClient<V> {
+    pub fn read<K: Serialize>(&self, key: K) -> Result<V, Error> {
+        match self.call(Request::Read { key })? {
+            Reply::ReadOk { value } => serde_json::from_value(value)
+                .map_err(|error| Error::InvalidValue(error.to_string())),
+            reply => Err(self.unexpected("read", reply)),
+        }
+    }
+
+    pub fn write<K: Serialize>(&self, key: K, value: V) -> Result<(), Error> {
+        match self.call(Request::Write { key, value })? {
+            Reply::WriteOk {} => Ok(()),
+            reply => Err(self.unexpected("write", reply)),
+        }
+    }
+
+    /// Sets `key` to `to` if it holds `from`. With `create_if_not_exists`,
+    /// a missing key is set to `to` as well rather than failing.
+    pub fn cas<K: Serialize>(
+        &self,
+        key: K,
+        from: V,
+        to: V,
+        create_if_not_exists: bool,
+    ) -> Result<(), Error> {
+        let request = Request::Cas {
+            key,
+            from,
+            to,
+            create_if_not_exists,
+        };
+        match self.call(request)? {
+            Reply::CasOk {} => Ok(()),
+            reply => Err(self.unexpected("cas", reply)),
+        }
+    }
+
+    fn unexpected(&self, request: &str, reply: Reply) -> Error {
+        Error::UnexpectedReply(format!(
+            "{} answered a {} with {:?}",
+            self.service, request, reply
+        ))
+    }
+
+    /// Sends `request` and waits for its reply, turning error replies into
+    /// errors.
+    fn call<K: Serialize>(&self, request: Request<K, V>) -> Result<Reply, Error> {
+        let (tx, rx) = mpsc::channel();
+        let msg_id = {
+            let mut calls = self.calls.lock().unwrap();
+            let msg_id = calls.next_msg_id;
+            calls.next_msg_id += 1;
+            calls.waiting.insert(msg_id, tx);
+            msg_id
+        };
+
+        let message = Message {
+            src: self.node_id.clone(),
+            dest: self.service.clone(),
+            body: Body {
+                msg_id: Some(msg_id),
+                in_reply_to: None,
+                payload: request,
+            },
+        };
+        write_to_stdout(&message);
+
+        match rx.recv_timeout(self.timeout) {
+            Ok(Reply::Error { code, text }) => Err(Error::from_code(code, text)),
+            Ok(reply) => Ok(reply),
+            Err(_) => {
+                self.calls.lock().unwrap().waiting.remove(&msg_id);
+                Err(Error::Timeout)
+            }
+        }
+    }
+}
+
+/// Writes the whole line under the stdout lock, so it cannot interleave
+/// with what the node writes.
+fn write_to_stdout<Payload: Serialize>(message: &Message<Payload>) {
+    let output = serde_json::to_string(message).unwrap();
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", output).unwrap();
+    stdout.flush().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    /// Makes `call` on another thread and answers it with `reply`.
+    fn answer<T: Send + 'static>(
+        client: &KvClient<u64>,
+        call: impl FnOnce(KvClient<u64>) -> T + Send + 'static,
+        reply: &str,
+    ) -> T {
+        let caller = client.clone();
+        let call = thread::spawn(move || call(caller));
+        while client.calls.lock().unwrap().waiting.is_empty() {
+            thread::yield_now();
+        }
+
+        let line = format!(
+            r#"{{"src":"lin-kv","dest":"n1","body":{{"in_reply_to":0,{}}}}}"#,
+            reply
+        );
+        assert!(client.handle(&line));
+        call.join().unwrap()
+    }
+
+    #[test]
+    fn reads_typed_values() {
+        let client = KvClient::lin_kv("n1");
+        let read = answer(&client, |c| c.read("k"), r#""type":"read_ok","value":3"#);
+        assert_eq!(read, Ok(3));
+    }
+
+    #[test]
+    fn error_codes_become_errors() {
+        let client = KvClient::lin_kv("n1");
+        let read = answer(
+            &client,
+            |c| c.read("k"),
+            r#""type":"error","code":20,"text":"not found""#,
+        );
+        assert_eq!(read, Err(Error::KeyDoesNotExist));
+    }
+
+    #[test]
+    fn a_reply_of_the_wrong_kind_is_an_error() {
+        let client = KvClient::lin_kv("n1");
+        let read = answer(&client, |c| c.read("k"), r#""type":"write_ok""#);
+        assert!(matches!(read, Err(Error::UnexpectedReply(_))));
+    }
+
+    #[test]
+    fn cas_asks_to_create_a_missing_key_when_told_to() {
+        let request: Request<&str, u64> = Request::Cas {
+            key: "k",
+            from: 1,
+            to: 2,
+            create_if_not_exists: true,
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "type": "cas",
+                "key": "k",
+                "from": 1,
+                "to": 2,
+                "create_if_not_exists": true,
+            })
+        );
+
+        let client = KvClient::lin_kv("n1");
+        let cas = answer(&client, |c| c.cas("k", 1, 2, true), r#""type":"cas_ok""#);
+        assert_eq!(cas, Ok(()));
+    }
+
+    #[test]
+    fn cas_on_a_changed_value_fails_its_precondition() {
+        let client = KvClient::lin_kv("n1");
+        let cas = answer(
+            &client,
+            |c| c.cas("k", 1, 2, false),
+            r#""type":"error","code":22,"text":"expected 1, found 3""#,
+        );
+        assert_eq!(cas, Err(Error::PreconditionFailed));
+    }
+
+    #[test]
+    fn call_without_a_reply_times_out_and_a_late_reply_is_dropped() {
+        let client = KvClient::<u64>::lin_kv("n1").with_timeout(Duration::from_millis(10));
+
+        assert_eq!(client.read("k"), Err(Error::Timeout));
+        assert!(client.calls.lock().unwrap().waiting.is_empty());
+
+        let late =
+            r#"{"src":"lin-kv","dest":"n1","body":{"in_reply_to":0,"type":"read_ok","value":3}}"#;
+        assert!(client.handle(late));
+        assert!(client.calls.lock().unwrap().waiting.is_empty());
+    }
+
+    #[test]
+    fn lines_not_from_the_service_are_left_to_the_node() {
+        let client = KvClient::<u64>::lin_kv("n1");
+
+        let from_a_client =
+            r#"{"src":"c1","dest":"n1","body":{"msg_id":1,"type":"read","key":"k"}}"#;
+        let from_another_service =
+            r#"{"src":"seq-kv","dest":"n1","body":{"in_reply_to":0,"type":"write_ok"}}"#;
+        assert!(!client.handle(from_a_client));
+        assert!(!client.handle(from_another_service));
+        assert!(!client.handle("not json"));
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt;
+
+const KEY_DOES_NOT_EXIST: u64 = 20;
+const PRECONDITION_FAILED: u64 = 22;
+
+/// Why a call to a kv service failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The key has never been written.
+    KeyDoesNotExist,
+    /// A `cas` found some value other than `from`.
+    PreconditionFailed,
+    /// No reply came in time. A write or cas may still have taken effect.
+    Timeout,
+    /// The service answered with a value that is not of the client's type.
+    InvalidValue(String),
+    /// The service answered with a reply meant for another kind of request.
+    UnexpectedReply(String),
+    /// Any other error the service returned.
+    Other { code: u64, text: String },
+}
+
+impl Error {
+    pub(crate) fn from_code(code: u64, text: String) -> Self {
+        match code {
+            KEY_DOES_NOT_EXIST => Error::KeyDoesNotExist,
+            PRECONDITION_FAILED => Error::PreconditionFailed,
+            code => Error::Other { code, text },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::KeyDoesNotExist => write!(f, "key does not exist"),
+            Error::PreconditionFailed => write!(f, "precondition failed"),
+            Error::Timeout => write!(f, "timed out"),
+            Error::InvalidValue(error) => write!(f, "invalid value: {}", error),
+            Error::UnexpectedReply(reply) => write!(f, "unexpected reply: {}", reply),
+            Error::Other { code, text } => write!(f, "error {}: {}", code, text),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,31 @@
+//! A client for Maelstrom's built-in key-value services, `lin-kv`, `seq-kv`
+//! and `lww-kv`, see maelstrom/doc/services.md. Nodes get typed `read`,
+//! `write` and `cas` calls instead of building the RPCs and matching up the
+//! replies themselves.
+
+mod client;
+mod error;
+
+pub use client::KvClient;
+pub use error::Error;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Body<Payload> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    msg_id: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    in_reply_to: Option<u64>,
+
+    #[serde(flatten)]
+    payload: Payload,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct Message<Payload> {
+    src: String,
+    dest: String,
+    body: Body<Payload>,
+}
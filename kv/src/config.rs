@@ -0,0 +1,153 @@
+use std::{env, num::NonZeroUsize, str::FromStr, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReadMode {
+    /// Reads go through the log like writes.
+    Log,
+    /// The leader notes its commit index, confirms it is still leader with a
+    /// round of heartbeats, and answers once that index is applied.
+    ReadIndex,
+    /// Like `ReadIndex`, but while a majority has acknowledged the leader
+    /// within the last election timeout no other leader can have been
+    /// elected, so the heartbeat round is skipped.
+    Lease,
+}
+
+impl FromStr for ReadMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => Ok(ReadMode::Log),
+            "read_index" => Ok(ReadMode::ReadIndex),
+            "lease" => Ok(ReadMode::Lease),
+            _ => Err(format!("unknown read mode: {}", s)),
+        }
+    }
+}
+
+/// Runtime knobs, read from `KV_*` environment variables.
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    /// Applied entries the Raft log holds before it is compacted into a
+    /// snapshot.
+    pub(crate) snapshot_interval: usize,
+    pub(crate) read_mode: ReadMode,
+    /// How much shorter than the election timeout a lease is, to allow for
+    /// clocks running at different rates.
+    pub(crate) lease_drift: Duration,
+    /// How many of the nodes given at init, in order, the groups are placed
+    /// on to begin with; the rest wait to be added. All of them if `None`.
+    pub(crate) initial_voters: Option<usize>,
+    /// Ask for pre-votes before calling an election, so a node that could
+    /// not win does not push the term up and depose a working leader.
+    pub(crate) pre_vote: bool,
+    /// Have a leader step down once a majority has not answered it for an
+    /// election timeout.
+    pub(crate) check_quorum: bool,
+    /// How many Raft groups the key space is split across.
+    pub(crate) groups: usize,
+    /// How many nodes each group runs on. All of them if `None`.
+    pub(crate) group_size: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            snapshot_interval: 1000,
+            read_mode: ReadMode::ReadIndex,
+            lease_drift: Duration::from_millis(200),
+            initial_voters: None,
+            pre_vote: false,
+            check_quorum: false,
+            groups: 1,
+            group_size: None,
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn from_env() -> Config {
+        Config::from_vars(|key| env::var(key).ok())
+    }
+
+    /// Reads the knobs through `lookup`, so tests need not set the
+    /// process's environment.
+    fn from_vars(lookup: impl Fn(&str) -> Option<String>) -> Config {
+        let mut config = Config::default();
+
+        if let Some(entries) = read_var(&lookup, "KV_SNAPSHOT_INTERVAL") {
+            config.snapshot_interval = entries;
+        }
+        if let Some(read_mode) = read_var(&lookup, "KV_READ_MODE") {
+            config.read_mode = read_mode;
+        }
+        if let Some(ms) = read_var(&lookup, "KV_LEASE_DRIFT_MS") {
+            config.lease_drift = Duration::from_millis(ms);
+        }
+        // zero voters, groups or group members would leave keys nowhere to go
+        if let Some(voters) = read_var::<NonZeroUsize>(&lookup, "KV_INITIAL_VOTERS") {
+            config.initial_voters = Some(voters.get());
+        }
+        if let Some(pre_vote) = read_var(&lookup, "KV_PRE_VOTE") {
+            config.pre_vote = pre_vote;
+        }
+        if let Some(check_quorum) = read_var(&lookup, "KV_CHECK_QUORUM") {
+            config.check_quorum = check_quorum;
+        }
+        if let Some(groups) = read_var::<NonZeroUsize>(&lookup, "KV_GROUPS") {
+            config.groups = groups.get();
+        }
+        if let Some(size) = read_var::<NonZeroUsize>(&lookup, "KV_GROUP_SIZE") {
+            config.group_size = Some(size.get());
+        }
+
+        config
+    }
+}
+
+fn read_var<T: FromStr>(lookup: &impl Fn(&str) -> Option<String>, key: &str) -> Option<T> {
+    let value = lookup(key)?;
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => panic!("invalid value for {}: {}", key, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from(vars: &[(&str, &str)]) -> Config {
+        Config::from_vars(|key| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn groups_are_read_from_the_environment() {
+        let config = from(&[("KV_GROUPS", "3"), ("KV_GROUP_SIZE", "2")]);
+        assert_eq!(config.groups, 3);
+        assert_eq!(config.group_size, Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid value for KV_GROUPS: 0")]
+    fn zero_groups_are_rejected() {
+        from(&[("KV_GROUPS", "0")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid value for KV_GROUP_SIZE: 0")]
+    fn empty_groups_are_rejected() {
+        from(&[("KV_GROUP_SIZE", "0")]);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid value for KV_INITIAL_VOTERS: 0")]
+    fn zero_initial_voters_are_rejected() {
+        from(&[("KV_INITIAL_VOTERS", "0")]);
+    }
+}
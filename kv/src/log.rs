@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{membership::Membership, Body, Message};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub term: u64,
+    /// The client request to apply once the entry is committed.
+    pub op: Option<Message<Body>>,
+    /// The membership from this entry on. Unlike ops, it takes effect as
+    /// soon as it is in the log.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub membership: Option<Membership>,
+}
+
+/// The Raft log. Indices start at 1, as in the paper.
+///
+/// Entries up to `base` have been compacted into a snapshot. Only the entry
+/// at `base` itself is kept, without its op, so there is always a previous
+/// entry whose term can be compared; a fresh log has a placeholder from
+/// term 0 at index 1.
+#[derive(Debug)]
+pub(crate) struct Log {
+    base: usize,
+    /// The entries from `base` on.
+    entries: Vec<Entry>,
+}
+
+impl Default for Log {
+    fn default() -> Self {
+        Log {
+            base: 1,
+            entries: vec![Entry {
+                term: 0,
+                op: None,
+                membership: None,
+            }],
+        }
+    }
+}
+
+impl Log {
+    /// `None` for indices past the end or compacted away.
+    pub(crate) fn get(&self, index: usize) -> Option<&Entry> {
+        index
+            .checked_sub(self.base)
+            .and_then(|i| self.entries.get(i))
+    }
+
+    pub(crate) fn last(&self) -> &Entry {
+        self.entries.last().expect("the log is never empty")
+    }
+
+    /// Index of the last entry.
+    pub(crate) fn size(&self) -> usize {
+        self.base + self.entries.len() - 1
+    }
+
+    /// Index of the last entry covered by the snapshot.
+    pub(crate) fn base(&self) -> usize {
+        self.base
+    }
+
+    pub(crate) fn append(&mut self, entry: Entry) {
+        self.entries.push(entry);
+    }
+
+    /// The latest membership change at or before `index`, with its index.
+    /// `None` if the log since `base` holds none, in which case the
+    /// snapshot's membership applies.
+    pub(crate) fn membership(&self, index: usize) -> Option<(usize, &Membership)> {
+        let end = (index + 1)
+            .saturating_sub(self.base)
+            .min(self.entries.len());
+        self.entries[..end]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, entry)| Some((self.base + i, entry.membership.as_ref()?)))
+    }
+
+    /// Entries from `index` on, which must be past `base`.
+    pub(crate) fn entries_from(&self, index: usize) -> &[Entry] {
+        let start = (index - self.base).min(self.entries.len());
+        &self.entries[start..]
+    }
+
+    /// Writes `entries` starting at `index`. Entries already there are kept
+    /// unless one disagrees on its term, in which case it and everything
+    /// after it are replaced. Entries up to `base` are committed, so they
+    /// are skipped.
+    pub(crate) fn merge(&mut self, index: usize, entries: Vec<Entry>) {
+        for (offset, entry) in entries.into_iter().enumerate() {
+            let Some(i) = (index + offset).checked_sub(self.base) else {
+                continue;
+            };
+            if i == 0 {
+                continue;
+            }
+
+            match self.entries.get(i) {
+                Some(existing) if existing.term == entry.term => {}
+                Some(_) => {
+                    self.entries.truncate(i);
+                    self.entries.push(entry);
+                }
+                None => self.entries.push(entry),
+            }
+        }
+    }
+
+    /// Drops the entries before `index`, which a snapshot now covers.
+    pub(crate) fn compact(&mut self, index: usize) {
+        if index <= self.base {
+            return;
+        }
+
+        self.entries.drain(..index - self.base);
+        self.entries[0].op = None;
+        self.base = index;
+    }
+
+    /// Makes the log start from a snapshot up to `index` from `term`. Entries
+    /// after it are kept if the log agrees with the snapshot, and dropped
+    /// otherwise.
+    pub(crate) fn reset(&mut self, index: usize, term: u64) {
+        if self.get(index).is_some_and(|entry| entry.term == term) {
+            self.compact(index);
+            return;
+        }
+
+        self.base = index;
+        self.entries = vec![Entry {
+            term,
+            op: None,
+            membership: None,
+        }];
+    }
+}
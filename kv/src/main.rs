@@ -1,18 +1,30 @@
+mod config;
+mod log;
+mod membership;
 mod node;
+mod raft;
+mod shard;
 mod storage;
 
-use std::{collections::HashMap, fmt::Debug, io::Write};
+use std::{fmt::Debug, io::Write, time::Duration};
 
 use anyhow::Context;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use storage::Storage;
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
     sync::mpsc::{channel, Receiver, Sender},
-    task,
+    task, time,
 };
 
+use config::Config;
+use log::Entry;
+use membership::Membership;
 use node::Node;
+use raft::Raft;
+use shard::Shards;
+
+/// How often timers are checked.
+const TICK: Duration = Duration::from_millis(10);
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Message<Body> {
@@ -28,10 +40,19 @@ pub struct Body {
 
     pub in_reply_to: Option<usize>,
 
+    /// The Raft group a message between nodes belongs to, or an admin
+    /// request is for. Client requests leave it out and go by key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<usize>,
+
     #[serde(flatten)]
     pub payload: Payload,
 }
 
+/// Keys and values are whatever JSON the client sends.
+pub type Key = serde_json::Value;
+pub type Value = serde_json::Value;
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
@@ -41,17 +62,109 @@ pub enum Payload {
         node_ids: Vec<String>,
     },
     InitOk {},
-    Topology {
-        topology: HashMap<String, Vec<String>>,
+    Read {
+        key: Key,
     },
-    TopologyOk {},
-    Broadcast {
-        message: u64,
-    },
-    BroadcastOk {},
-    Read {},
     ReadOk {
-        messages: Vec<u64>,
+        value: Value,
+    },
+    Write {
+        key: Key,
+        value: Value,
+    },
+    WriteOk {},
+    Cas {
+        key: Key,
+        from: Value,
+        to: Value,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+    CasOk {},
+    Error {
+        code: u64,
+        text: String,
+    },
+    /// Adds `node` to the cluster, first as a learner and then, once it has
+    /// caught up, as a voter.
+    AddNode {
+        node: String,
+    },
+    AddNodeOk {},
+    RemoveNode {
+        node: String,
+    },
+    RemoveNodeOk {},
+    /// Hands leadership over to `node`, or to the most up to date voter.
+    TransferLeadership {
+        #[serde(default)]
+        node: Option<String>,
+    },
+    TransferLeadershipOk {},
+    RequestVote {
+        term: u64,
+        candidate_id: String,
+        last_log_index: usize,
+        last_log_term: u64,
+        /// The election was called on the leader's behalf by `timeout_now`.
+        #[serde(default)]
+        leadership_transfer: bool,
+    },
+    RequestVoteRes {
+        term: u64,
+        vote_granted: bool,
+    },
+    /// Asks whether a vote at `term` would be granted, without anyone
+    /// moving to it.
+    PreVote {
+        term: u64,
+        candidate_id: String,
+        last_log_index: usize,
+        last_log_term: u64,
+    },
+    PreVoteRes {
+        term: u64,
+        vote_granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        leader_id: String,
+        prev_log_index: usize,
+        prev_log_term: u64,
+        entries: Vec<Entry>,
+        leader_commit: usize,
+    },
+    AppendEntriesRes {
+        term: u64,
+        success: bool,
+        /// Index of the last entry the follower now shares with the leader.
+        match_index: usize,
+    },
+    /// A chunk of the leader's snapshot, hex encoded, starting `offset`
+    /// bytes in.
+    InstallSnapshot {
+        term: u64,
+        leader_id: String,
+        last_included_index: usize,
+        last_included_term: u64,
+        offset: usize,
+        data: String,
+        done: bool,
+        /// The membership as of `last_included_index`.
+        membership: Membership,
+    },
+    /// How many bytes of the snapshot the follower holds, or `done` once it
+    /// has installed it.
+    InstallSnapshotRes {
+        term: u64,
+        last_included_index: usize,
+        offset: usize,
+        done: bool,
+    },
+    /// Sent by a leader handing over leadership: the follower, now up to
+    /// date, starts an election without waiting for its timeout.
+    TimeoutNow {
+        term: u64,
     },
 }
 
@@ -65,13 +178,9 @@ async fn main() -> anyhow::Result<()> {
     let node = Node::default();
     let node = init_node(node, writer_tx.clone()).await;
 
-    let mut storage = Storage::default();
-
     let reader_task = task::spawn(async move { read_from_stdin(reader_tx).await });
     let handler_task =
-        task::spawn(
-            async move { handle_messages(node, &mut storage, &mut reader_rx, writer_tx).await },
-        );
+        task::spawn(async move { handle_messages(node, &mut reader_rx, writer_tx).await });
     let writer_task = task::spawn(async move { write_to_stdout(&mut writer_rx).await });
 
     let _ = tokio::try_join!(reader_task, handler_task, writer_task);
@@ -92,21 +201,19 @@ async fn init_node(node: Node, writer_tx: Sender<Message<Body>>) -> Node {
 
     let node = node.init(message.clone());
 
-    match message.body.payload {
-        Payload::Init { node_id, .. } => {
-            let reply = Message {
-                src: node_id,
-                dest: message.src.clone(),
-                body: Body {
-                    id: Some(0),
-                    in_reply_to: message.body.id,
-                    payload: Payload::InitOk {},
-                },
-            };
-
-            writer_tx.send(reply).await.unwrap();
-        }
-        _ => (),
+    if let Payload::Init { node_id, .. } = message.body.payload {
+        let reply = Message {
+            src: node_id,
+            dest: message.src.clone(),
+            body: Body {
+                id: Some(0),
+                in_reply_to: message.body.id,
+                group: None,
+                payload: Payload::InitOk {},
+            },
+        };
+
+        writer_tx.send(reply).await.unwrap();
     }
 
     node
@@ -143,58 +250,136 @@ async fn write_to_stdout<T: Debug + Serialize>(writer_rx: &mut Receiver<T>) {
 }
 
 async fn handle_messages(
-    node: Node,
-    storage: &mut Storage,
+    mut node: Node,
     reader_rx: &mut Receiver<Message<Body>>,
     writer_tx: Sender<Message<Body>>,
 ) {
-    while let Some(input) = reader_rx.recv().await {
-        match input.body.payload {
-            Payload::Topology { .. } => {
-                let reply = Message {
-                    src: node.id.clone(),
-                    dest: input.src,
-                    body: Body {
-                        id: input.body.id,
-                        in_reply_to: input.body.id,
-                        payload: Payload::TopologyOk {},
-                    },
-                };
-
-                writer_tx.send(reply).await.unwrap();
-            }
-            Payload::Broadcast { message, .. } => {
-                storage.add_message(message);
-                let reply = Message {
-                    src: node.id.clone(),
-                    dest: input.src,
-                    body: Body {
-                        id: input.body.id,
-                        in_reply_to: input.body.id,
-                        payload: Payload::BroadcastOk {},
-                    },
-                };
-
-                writer_tx.send(reply).await.unwrap();
-            }
-            Payload::Read {} => {
-                let reply = Message {
-                    src: node.id.clone(),
-                    dest: input.src,
-                    body: Body {
-                        id: input.body.id,
-                        in_reply_to: input.body.id,
-                        payload: Payload::ReadOk {
-                            messages: storage.get_messages(),
-                        },
-                    },
-                };
-
-                writer_tx.send(reply).await.unwrap();
-            }
-            _ => {
-                panic!("unknown variant")
-            }
+    let mut shards = Shards::new(&node, Config::from_env());
+    let mut ticker = time::interval(TICK);
+
+    loop {
+        let to_send = tokio::select! {
+            input = reader_rx.recv() => match input {
+                Some(input) => shards.handle(&mut node, input),
+                None => break,
+            },
+            _ = ticker.tick() => shards.tick(&mut node),
+        };
+
+        for message in to_send {
+            writer_tx.send(message).await.unwrap();
         }
     }
 }
+
+/// Hands a message for one Raft group to that group's `raft`.
+fn handle_message(node: &mut Node, raft: &mut Raft, input: Message<Body>) -> Vec<Message<Body>> {
+    let payload = match input.body.payload.clone() {
+        Payload::Read { .. }
+        | Payload::Write { .. }
+        | Payload::Cas { .. }
+        | Payload::AddNode { .. }
+        | Payload::RemoveNode { .. }
+        | Payload::TransferLeadership { .. } => {
+            return raft.on_client_request(node, input);
+        }
+        Payload::RequestVote {
+            term,
+            candidate_id,
+            last_log_index,
+            last_log_term,
+            leadership_transfer,
+        } => raft.on_request_vote(
+            term,
+            candidate_id,
+            last_log_index,
+            last_log_term,
+            leadership_transfer,
+        ),
+        Payload::RequestVoteRes { term, vote_granted } => {
+            raft.on_vote(node, input.src, term, vote_granted);
+            return vec![];
+        }
+        Payload::PreVote {
+            term,
+            last_log_index,
+            last_log_term,
+            ..
+        } => raft.on_pre_vote(term, last_log_index, last_log_term),
+        Payload::PreVoteRes { term, vote_granted } => {
+            return raft.on_pre_vote_res(node, input.src, term, vote_granted);
+        }
+        Payload::ReadOk { .. }
+        | Payload::WriteOk { .. }
+        | Payload::CasOk { .. }
+        | Payload::AddNodeOk { .. }
+        | Payload::RemoveNodeOk { .. }
+        | Payload::TransferLeadershipOk { .. }
+        | Payload::Error { .. } => {
+            return raft.on_proxied_reply(node, input);
+        }
+        Payload::AppendEntries {
+            term,
+            leader_id,
+            prev_log_index,
+            prev_log_term,
+            entries,
+            leader_commit,
+        } => raft.on_append_entries(
+            term,
+            leader_id,
+            prev_log_index,
+            prev_log_term,
+            entries,
+            leader_commit,
+        ),
+        Payload::AppendEntriesRes {
+            term,
+            success,
+            match_index,
+        } => {
+            raft.on_append_entries_res(
+                input.src,
+                input.body.in_reply_to,
+                term,
+                success,
+                match_index,
+            );
+            return vec![];
+        }
+        Payload::InstallSnapshot {
+            term,
+            leader_id,
+            last_included_index,
+            last_included_term,
+            offset,
+            data,
+            done,
+            membership,
+        } => raft.on_install_snapshot(
+            term,
+            leader_id,
+            last_included_index,
+            last_included_term,
+            offset,
+            data,
+            done,
+            membership,
+        ),
+        Payload::InstallSnapshotRes {
+            term,
+            last_included_index,
+            offset,
+            done,
+        } => {
+            raft.on_install_snapshot_res(input.src, term, last_included_index, offset, done);
+            return vec![];
+        }
+        Payload::TimeoutNow { term } => return raft.on_timeout_now(node, term),
+        _ => {
+            panic!("unknown variant")
+        }
+    };
+
+    vec![node.reply(&input, payload)]
+}
@@ -0,0 +1,87 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+/// The nodes taking part in the cluster as of some log entry.
+///
+/// A change of voters goes through joint consensus: first to a joint
+/// configuration where decisions need a majority of both the old and the
+/// new voters, then, once that is committed, to the new voters alone.
+/// Adding learners or dropping them changes no majority, so it takes one
+/// step.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Membership {
+    /// Nodes whose votes and acknowledgements count.
+    pub(crate) voters: BTreeSet<String>,
+    /// While joint: the voters of the configuration being left.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) old_voters: Option<BTreeSet<String>>,
+    /// Nodes that are sent the log but do not vote, until they have caught
+    /// up and are promoted.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub(crate) learners: BTreeSet<String>,
+}
+
+impl Membership {
+    pub(crate) fn new(voters: impl IntoIterator<Item = String>) -> Self {
+        Membership {
+            voters: voters.into_iter().collect(),
+            old_voters: None,
+            learners: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn is_joint(&self) -> bool {
+        self.old_voters.is_some()
+    }
+
+    /// Voters of either configuration.
+    pub(crate) fn voters(&self) -> BTreeSet<&String> {
+        self.voters
+            .iter()
+            .chain(self.old_voters.iter().flatten())
+            .collect()
+    }
+
+    /// Everyone the leader replicates to.
+    pub(crate) fn members(&self) -> BTreeSet<&String> {
+        let mut members = self.voters();
+        members.extend(&self.learners);
+        members
+    }
+
+    pub(crate) fn is_voter(&self, id: &str) -> bool {
+        self.voters().iter().any(|voter| *voter == id)
+    }
+
+    pub(crate) fn contains(&self, id: &str) -> bool {
+        self.members().iter().any(|member| *member == id)
+    }
+
+    /// The highest value a majority of the voters have reached, of both
+    /// configurations while joint. Voters without a value are behind
+    /// everyone; `None` if too many of them are.
+    pub(crate) fn quorum_value<T: Ord + Copy>(
+        &self,
+        value: impl Fn(&str) -> Option<T>,
+    ) -> Option<T> {
+        let quorum_value = |voters: &BTreeSet<String>| {
+            let mut values: Vec<T> = voters.iter().filter_map(|voter| value(voter)).collect();
+            values.sort_unstable_by(|a, b| b.cmp(a));
+            values.get(voters.len() / 2).copied()
+        };
+
+        let new = quorum_value(&self.voters)?;
+        match &self.old_voters {
+            Some(old_voters) => quorum_value(old_voters).map(|old| old.min(new)),
+            None => Some(new),
+        }
+    }
+
+    /// A majority of the voters, of both configurations while joint, are in
+    /// `agreed`.
+    pub(crate) fn is_quorum(&self, agreed: impl Fn(&str) -> bool) -> bool {
+        self.quorum_value(|voter| agreed(voter).then_some(()))
+            .is_some()
+    }
+}
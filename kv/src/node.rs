@@ -3,21 +3,49 @@ use crate::{Body, Message, Payload};
 #[derive(Debug, Default)]
 pub(crate) struct Node {
     pub(crate) id: String,
-    pub(crate) availble_nodes: Vec<String>,
+    pub(crate) node_ids: Vec<String>,
+    next_msg_id: usize,
 }
 
 impl Node {
     pub(crate) fn init(&self, message: Message<Body>) -> Node {
         match message.body.payload {
-            Payload::Init {
-                node_id, node_ids, ..
-            } => {
-                return Node {
-                    id: node_id,
-                    availble_nodes: node_ids,
-                }
-            }
+            Payload::Init { node_id, node_ids } => Node {
+                id: node_id,
+                node_ids,
+                next_msg_id: 0,
+            },
             _ => panic!("unknown variant"),
         }
     }
+
+    /// A new request from this node to `dest`.
+    pub(crate) fn request(&mut self, dest: &str, payload: Payload) -> Message<Body> {
+        self.next_msg_id += 1;
+
+        Message {
+            src: self.id.clone(),
+            dest: dest.to_string(),
+            body: Body {
+                id: Some(self.next_msg_id),
+                in_reply_to: None,
+                group: None,
+                payload,
+            },
+        }
+    }
+
+    /// The answer to `input`.
+    pub(crate) fn reply(&self, input: &Message<Body>, payload: Payload) -> Message<Body> {
+        Message {
+            src: self.id.clone(),
+            dest: input.src.clone(),
+            body: Body {
+                id: input.body.id,
+                in_reply_to: input.body.id,
+                group: None,
+                payload,
+            },
+        }
+    }
 }
@@ -0,0 +1,2086 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    config::{Config, ReadMode},
+    log::{Entry, Log},
+    membership::Membership,
+    node::Node,
+    storage::{Storage, PRECONDITION_FAILED},
+    Body, Message, Payload,
+};
+
+/// Shortest time without hearing from a leader before calling an election.
+/// Each deadline adds up to as much again at random, so nodes rarely time
+/// out together and split the vote.
+const ELECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Longest a leader stays silent towards a follower; well under the
+/// election timeout so followers keep following.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Shortest time between two rounds of `append_entries`, so a burst of
+/// client requests is shipped in a few batches rather than one by one.
+const MIN_REPLICATION_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a follower waits for the leader to answer a request it passed
+/// on, or a membership change waits to be committed. After that the client
+/// has given up too, so the request is forgotten; it may still take effect,
+/// so the client gets no error either.
+pub(crate) const PROXY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most snapshot bytes sent in one `install_snapshot` message, before hex
+/// encoding doubles them.
+const SNAPSHOT_CHUNK_SIZE: usize = 16 * 1024;
+
+pub(crate) const TEMPORARILY_UNAVAILABLE: u64 = 11;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    Follower,
+    /// Asking for pre-votes, still in the term it started from.
+    PreCandidate,
+    Candidate,
+    Leader,
+}
+
+/// The state machine as of a log index, encoded by `Storage::snapshot`.
+#[derive(Debug)]
+struct Snapshot {
+    last_included_index: usize,
+    last_included_term: u64,
+    data: Vec<u8>,
+    membership: Membership,
+}
+
+/// A leadership transfer this node took in, while it hands leadership over
+/// and until it hears how that went.
+#[derive(Debug)]
+struct Transfer {
+    request: Message<Body>,
+    target: String,
+    /// When to give up and take client requests again.
+    deadline: Instant,
+    timeout_sent: bool,
+}
+
+/// A read the leader answers from its state machine without going through
+/// the log.
+#[derive(Debug)]
+struct PendingRead {
+    request: Message<Body>,
+    /// The commit index when the read arrived; the read sees at least this.
+    read_index: usize,
+    /// A majority must have acknowledged heartbeats sent after this, proving
+    /// we were still leader when the read arrived. `None` if a lease already
+    /// proved it.
+    confirm_after: Option<Instant>,
+}
+
+/// Raft consensus state (Ongaro and Ousterhout, "In Search of an
+/// Understandable Consensus Algorithm"), replicating kv requests into
+/// `storage`. Handlers return the messages to send rather than sending them.
+#[derive(Debug)]
+pub(crate) struct Raft {
+    pub(crate) role: Role,
+    pub(crate) term: u64,
+    voted_for: Option<String>,
+    /// The leader of the current term, once we have heard from it.
+    leader: Option<String>,
+    /// Nodes that voted for us in the current term, while a candidate, or
+    /// that would, while a pre-candidate.
+    votes: HashSet<String>,
+    pub(crate) log: Log,
+    /// The latest membership in the log, or the snapshot's if the log has
+    /// none, and the index it is from.
+    membership: Membership,
+    membership_index: usize,
+    /// Highest log index known to be stored on a majority.
+    commit_index: usize,
+    /// Highest log index applied to `storage`.
+    last_applied: usize,
+    storage: Storage,
+    /// `storage` as of the start of the log, for followers that need
+    /// entries we have compacted away.
+    snapshot: Snapshot,
+    config: Config,
+    /// A snapshot being received from the leader: its last included index
+    /// and the bytes so far.
+    receiving: Option<(usize, Vec<u8>)>,
+    election_deadline: Instant,
+    /// While leader: the next log index to send each follower.
+    next_index: HashMap<String, usize>,
+    /// While leader: the highest log index each follower is known to share
+    /// with us.
+    match_index: HashMap<String, usize>,
+    /// While leader: when each follower was last sent `append_entries`.
+    last_append: HashMap<String, Instant>,
+    /// While leader: how much of the current snapshot each follower that
+    /// needs it has acknowledged.
+    snapshot_sent: HashMap<String, usize>,
+    last_replication: Option<Instant>,
+    /// While leader: index of the first entry of our term.
+    term_start: usize,
+    /// While leader: follower and send time of each `append_entries` not
+    /// answered yet, by msg_id.
+    inflight: HashMap<usize, (String, Instant)>,
+    /// While leader: send time of the latest `append_entries` each follower
+    /// has answered.
+    acked_at: HashMap<String, Instant>,
+    /// While leader: when each follower last answered us at all.
+    heard_from: HashMap<String, Instant>,
+    /// While leader: nodes a membership change dropped, with the index of
+    /// that change. They are still sent the log until they have it, or they
+    /// would go on calling elections as voters of the old configuration.
+    departing: HashMap<String, usize>,
+    pending_reads: Vec<PendingRead>,
+    /// When we last accepted a leader's `append_entries` or snapshot.
+    leader_contact: Option<Instant>,
+    /// Client requests passed on to the leader, by the msg_id they were
+    /// sent under, with when they were sent.
+    proxied: HashMap<usize, (Message<Body>, Instant)>,
+    /// `add_node` and `remove_node` requests waiting for their change to be
+    /// committed, with when they arrived.
+    pending_changes: Vec<(Message<Body>, Instant)>,
+    transfer: Option<Transfer>,
+    clock: Arc<dyn Clock>,
+    /// Draws the random part of election timeouts.
+    rng: StdRng,
+}
+
+/// Where Raft reads the time from, so tests can move it along themselves.
+/// The groups a node runs share one.
+pub(crate) trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+impl Raft {
+    /// A node reading the time from `clock` and drawing its election
+    /// timeouts from `rng`.
+    pub(crate) fn new(
+        config: Config,
+        membership: Membership,
+        clock: Arc<dyn Clock>,
+        rng: StdRng,
+    ) -> Self {
+        let storage = Storage::default();
+        let log = Log::default();
+        let snapshot = Snapshot {
+            last_included_index: log.base(),
+            last_included_term: log.last().term,
+            data: storage.snapshot(),
+            membership: membership.clone(),
+        };
+
+        let mut raft = Raft {
+            role: Role::Follower,
+            term: 0,
+            voted_for: None,
+            leader: None,
+            votes: HashSet::new(),
+            membership_index: log.base(),
+            membership,
+            log,
+            commit_index: 0,
+            last_applied: 0,
+            storage,
+            snapshot,
+            config,
+            receiving: None,
+            election_deadline: clock.now(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            last_append: HashMap::new(),
+            snapshot_sent: HashMap::new(),
+            last_replication: None,
+            term_start: 0,
+            inflight: HashMap::new(),
+            acked_at: HashMap::new(),
+            heard_from: HashMap::new(),
+            departing: HashMap::new(),
+            pending_reads: Vec::new(),
+            leader_contact: None,
+            proxied: HashMap::new(),
+            pending_changes: Vec::new(),
+            transfer: None,
+            clock,
+            rng,
+        };
+        raft.election_deadline = raft.election_deadline();
+        raft
+    }
+
+    /// A randomized deadline for the next election.
+    fn election_deadline(&mut self) -> Instant {
+        self.clock.now() + ELECTION_TIMEOUT.mul_f64(1.0 + self.rng.gen::<f64>())
+    }
+
+    pub(crate) fn membership(&self) -> &Membership {
+        &self.membership
+    }
+
+    /// Runs the election and replication timers.
+    pub(crate) fn tick(&mut self, node: &mut Node) -> Vec<Message<Body>> {
+        let mut to_send = Vec::new();
+
+        if self.clock.now() >= self.election_deadline {
+            // nodes outside the voters could not win, and would only
+            // disrupt those who can
+            if self.role == Role::Leader || !self.membership.is_voter(&node.id) {
+                self.election_deadline = self.election_deadline();
+            } else if self.config.pre_vote {
+                to_send.extend(self.become_pre_candidate(node));
+            } else {
+                to_send.extend(self.become_candidate(node, false));
+            }
+        }
+
+        // a majority may have elected someone else by now, and requests we
+        // take in could not be committed anyway
+        if self.role == Role::Leader && self.config.check_quorum && self.lost_quorum(node) {
+            self.become_follower();
+        }
+
+        if self.role == Role::Leader {
+            to_send.extend(self.replicate(node));
+            self.advance_commit_index(node);
+            to_send.extend(self.advance_membership(node));
+        }
+        to_send.extend(self.apply(node));
+        to_send.extend(self.serve_reads(node));
+        to_send.extend(self.answer_changes(node));
+        to_send.extend(self.advance_transfer(node));
+        self.maybe_snapshot();
+
+        // answers this old can no longer confirm anything a pending read or
+        // a lease needs
+        let now = self.clock.now();
+        self.inflight
+            .retain(|_, (_, sent_at)| now.duration_since(*sent_at) < ELECTION_TIMEOUT);
+
+        self.proxied
+            .retain(|_, (_, sent_at)| now.duration_since(*sent_at) < PROXY_TIMEOUT);
+
+        to_send
+    }
+
+    fn become_follower(&mut self) {
+        self.role = Role::Follower;
+        self.leader = None;
+        self.next_index.clear();
+        self.match_index.clear();
+        self.last_append.clear();
+        self.snapshot_sent.clear();
+        self.inflight.clear();
+        self.acked_at.clear();
+        self.heard_from.clear();
+        self.departing.clear();
+        self.election_deadline = self.election_deadline();
+    }
+
+    fn become_pre_candidate(&mut self, node: &mut Node) -> Vec<Message<Body>> {
+        self.role = Role::PreCandidate;
+        self.leader = None;
+        self.votes = HashSet::from([node.id.clone()]);
+        self.election_deadline = self.election_deadline();
+
+        if self
+            .membership
+            .is_quorum(|voter| self.votes.contains(voter))
+        {
+            return self.become_candidate(node, false);
+        }
+
+        let request = Payload::PreVote {
+            term: self.term + 1,
+            candidate_id: node.id.clone(),
+            last_log_index: self.log.size(),
+            last_log_term: self.log.last().term,
+        };
+        self.request_votes(node, request)
+    }
+
+    fn become_candidate(
+        &mut self,
+        node: &mut Node,
+        leadership_transfer: bool,
+    ) -> Vec<Message<Body>> {
+        self.role = Role::Candidate;
+        self.leader = None;
+        self.advance_term(self.term + 1);
+        self.voted_for = Some(node.id.clone());
+        self.votes = HashSet::from([node.id.clone()]);
+        self.election_deadline = self.election_deadline();
+
+        if self
+            .membership
+            .is_quorum(|voter| self.votes.contains(voter))
+        {
+            self.become_leader(node);
+            return vec![];
+        }
+
+        let request = Payload::RequestVote {
+            term: self.term,
+            candidate_id: node.id.clone(),
+            last_log_index: self.log.size(),
+            last_log_term: self.log.last().term,
+            leadership_transfer,
+        };
+        self.request_votes(node, request)
+    }
+
+    /// Sends `request` to every other voter.
+    fn request_votes(&self, node: &mut Node, request: Payload) -> Vec<Message<Body>> {
+        let others: Vec<&String> = self
+            .membership
+            .voters()
+            .into_iter()
+            .filter(|voter| **voter != node.id)
+            .collect();
+        others
+            .into_iter()
+            .map(|other| node.request(other, request.clone()))
+            .collect()
+    }
+
+    fn become_leader(&mut self, node: &Node) {
+        self.role = Role::Leader;
+        self.votes.clear();
+        self.last_replication = None;
+        // everyone gets an election timeout to answer before we count them
+        // as lost
+        let now = self.clock.now();
+        self.heard_from = self
+            .membership
+            .voters()
+            .into_iter()
+            .map(|voter| (voter.clone(), now))
+            .collect();
+        for other in self.membership.members() {
+            if *other != node.id {
+                self.next_index.insert(other.clone(), self.log.size() + 1);
+                self.match_index.insert(other.clone(), 0);
+            }
+        }
+
+        // reads can only be served from our commit index once an entry of
+        // our own term is committed, so commit an empty one right away
+        self.log.append(Entry {
+            term: self.term,
+            op: None,
+            membership: None,
+        });
+        self.term_start = self.log.size();
+    }
+
+    fn advance_term(&mut self, term: u64) {
+        assert!(self.term < term, "term can't go backwards");
+        self.term = term;
+        self.voted_for = None;
+    }
+
+    /// Moves to `remote_term` and follows if another node is ahead of us.
+    pub(crate) fn maybe_step_down(&mut self, remote_term: u64) {
+        if self.term < remote_term {
+            self.advance_term(remote_term);
+            self.become_follower();
+        }
+    }
+
+    /// Like `maybe_step_down`, for an answer from `src`. A node a membership
+    /// change dropped counts for nothing any more, so a term it ran up
+    /// calling elections while cut off does not depose us.
+    fn maybe_step_down_for(&mut self, src: &str, remote_term: u64) {
+        if self.membership.contains(src) {
+            self.maybe_step_down(remote_term);
+        }
+    }
+
+    /// Grants a vote unless we already voted for someone else this term or
+    /// our log is more up to date than the candidate's.
+    pub(crate) fn on_request_vote(
+        &mut self,
+        term: u64,
+        candidate_id: String,
+        last_log_index: usize,
+        last_log_term: u64,
+        leadership_transfer: bool,
+    ) -> Payload {
+        // while a leader is alive as far as we know, votes are refused
+        // without even looking at the term: a node that was cut off or
+        // removed would otherwise depose a working leader, and a leader
+        // holding a lease relies on its followers not electing anyone else
+        // until it runs out. A transfer target may still be voted in: the
+        // leader handing over turns reads away for as long as the transfer
+        // runs, and forgets its acknowledgements if it gives up
+        if self.leader_alive() && !leadership_transfer {
+            return Payload::RequestVoteRes {
+                term: self.term,
+                vote_granted: false,
+            };
+        }
+
+        self.maybe_step_down(term);
+
+        let ours = (self.log.last().term, self.log.size());
+        let vote_granted = term == self.term
+            && self
+                .voted_for
+                .as_ref()
+                .is_none_or(|voted_for| *voted_for == candidate_id)
+            && (last_log_term, last_log_index) >= ours;
+
+        if vote_granted {
+            self.voted_for = Some(candidate_id);
+            self.election_deadline = self.election_deadline();
+        }
+
+        Payload::RequestVoteRes {
+            term: self.term,
+            vote_granted,
+        }
+    }
+
+    /// Grants a pre-vote if we would grant a vote at `term` and have not
+    /// heard from a leader lately. Our own state is left as it is, `term`
+    /// included.
+    pub(crate) fn on_pre_vote(
+        &self,
+        term: u64,
+        last_log_index: usize,
+        last_log_term: u64,
+    ) -> Payload {
+        let ours = (self.log.last().term, self.log.size());
+        let vote_granted =
+            self.term < term && !self.leader_alive() && (last_log_term, last_log_index) >= ours;
+
+        Payload::PreVoteRes {
+            term: self.term,
+            vote_granted,
+        }
+    }
+
+    /// Calls the election once a majority would vote for us.
+    pub(crate) fn on_pre_vote_res(
+        &mut self,
+        node: &mut Node,
+        src: String,
+        term: u64,
+        vote_granted: bool,
+    ) -> Vec<Message<Body>> {
+        // a voter ahead of us can only be won over at its term
+        self.maybe_step_down(term);
+
+        if self.role != Role::PreCandidate || !vote_granted {
+            return vec![];
+        }
+
+        self.votes.insert(src);
+        if self
+            .membership
+            .is_quorum(|voter| self.votes.contains(voter))
+        {
+            return self.become_candidate(node, false);
+        }
+
+        vec![]
+    }
+
+    /// We are leader, or followed one within the last election timeout.
+    fn leader_alive(&self) -> bool {
+        self.role == Role::Leader
+            || self
+                .leader_contact
+                .is_some_and(|at| self.clock.now().duration_since(at) < ELECTION_TIMEOUT)
+    }
+
+    /// No majority has answered us for an election timeout.
+    fn lost_quorum(&self, node: &Node) -> bool {
+        let now = self.clock.now();
+        let heard_from = self.membership.quorum_value(|voter| {
+            if voter == node.id {
+                Some(now)
+            } else {
+                self.heard_from.get(voter).copied()
+            }
+        });
+        heard_from.is_none_or(|at| now.duration_since(at) >= ELECTION_TIMEOUT)
+    }
+
+    pub(crate) fn on_vote(&mut self, node: &Node, src: String, term: u64, vote_granted: bool) {
+        self.maybe_step_down(term);
+
+        if self.role != Role::Candidate || term != self.term || !vote_granted {
+            return;
+        }
+
+        self.votes.insert(src);
+        if self
+            .membership
+            .is_quorum(|voter| self.votes.contains(voter))
+        {
+            self.become_leader(node);
+        }
+    }
+
+    /// Appends a client's kv request to the log, to be answered once
+    /// committed and applied, or starts the admin request it makes.
+    /// Followers pass requests on to the leader.
+    pub(crate) fn on_client_request(
+        &mut self,
+        node: &mut Node,
+        request: Message<Body>,
+    ) -> Vec<Message<Body>> {
+        if self.role == Role::Leader {
+            if self.transfer.is_some() {
+                let error = Payload::Error {
+                    code: TEMPORARILY_UNAVAILABLE,
+                    text: "leadership transfer in progress".to_string(),
+                };
+                return vec![node.reply(&request, error)];
+            }
+
+            match &request.body.payload {
+                Payload::Read { .. } if self.config.read_mode != ReadMode::Log => {
+                    return self.read(node, request);
+                }
+                Payload::AddNode { .. } | Payload::RemoveNode { .. } => {
+                    return self.change_membership(node, request);
+                }
+                Payload::TransferLeadership { .. } => {
+                    return self.transfer_leadership(node, request);
+                }
+                _ => {}
+            }
+
+            self.log.append(Entry {
+                term: self.term,
+                op: Some(request),
+                membership: None,
+            });
+            return vec![];
+        }
+
+        let Some(leader) = &self.leader else {
+            let error = Payload::Error {
+                code: TEMPORARILY_UNAVAILABLE,
+                text: "no leader known".to_string(),
+            };
+            return vec![node.reply(&request, error)];
+        };
+
+        let proxy = node.request(leader, request.body.payload.clone());
+        let msg_id = proxy.body.id.expect("requests always have a msg_id");
+        self.proxied.insert(msg_id, (request, self.clock.now()));
+        vec![proxy]
+    }
+
+    /// Hands the leader's answer to a request we passed on back to the
+    /// client that sent it.
+    pub(crate) fn on_proxied_reply(
+        &mut self,
+        node: &Node,
+        reply: Message<Body>,
+    ) -> Vec<Message<Body>> {
+        let request = reply
+            .body
+            .in_reply_to
+            .and_then(|msg_id| self.proxied.remove(&msg_id));
+
+        match request {
+            Some((request, _)) => vec![node.reply(&request, reply.body.payload)],
+            None => vec![],
+        }
+    }
+
+    /// Sends each follower the entries it lacks, or an empty heartbeat if it
+    /// has gone without one for too long.
+    fn replicate(&mut self, node: &mut Node) -> Vec<Message<Body>> {
+        let now = self.clock.now();
+        if self
+            .last_replication
+            .is_some_and(|at| now.duration_since(at) < MIN_REPLICATION_INTERVAL)
+        {
+            return vec![];
+        }
+        self.last_replication = Some(now);
+
+        let confirm_after = self
+            .pending_reads
+            .iter()
+            .filter_map(|read| read.confirm_after)
+            .max();
+
+        let match_index = &self.match_index;
+        self.departing.retain(|departed, index| {
+            match_index
+                .get(departed)
+                .is_none_or(|matched| matched < index)
+        });
+
+        let mut to_send = Vec::new();
+        let mut others = self.membership.members();
+        others.extend(self.departing.keys());
+        let others: Vec<String> = others
+            .into_iter()
+            .filter(|member| **member != node.id)
+            .cloned()
+            .collect();
+        for other in others {
+            // members added since we took over start from the oldest entry
+            // we have
+            let next_index = *self
+                .next_index
+                .entry(other.clone())
+                .or_insert(self.log.base() + 1);
+            let last_append = self.last_append.get(&other).copied();
+            let heartbeat_due = last_append.is_none_or(|at| now.duration_since(at) >= HEARTBEAT_INTERVAL)
+                // a pending read waits for this follower to hear from us
+                || confirm_after.is_some_and(|after| last_append.is_none_or(|at| at < after));
+
+            if next_index <= self.log.base() {
+                // the entries it needs are only in the snapshot. Chunks are
+                // large, so the next one waits for this one's answer, or
+                // for a heartbeat if the answer is lost
+                if heartbeat_due {
+                    to_send.push(self.send_snapshot(node, &other));
+                    self.last_append.insert(other, now);
+                }
+                continue;
+            }
+
+            let entries = self.log.entries_from(next_index).to_vec();
+            if entries.is_empty() && !heartbeat_due {
+                continue;
+            }
+
+            let prev_log_index = next_index - 1;
+            let request = Payload::AppendEntries {
+                term: self.term,
+                leader_id: node.id.clone(),
+                prev_log_index,
+                prev_log_term: self.log.get(prev_log_index).map_or(0, |entry| entry.term),
+                entries,
+                leader_commit: self.commit_index,
+            };
+            let message = node.request(&other, request);
+            let msg_id = message.body.id.expect("requests always have a msg_id");
+            to_send.push(message);
+            self.inflight.insert(msg_id, (other.clone(), now));
+            self.last_append.insert(other, now);
+        }
+
+        to_send
+    }
+
+    /// Accepts entries from the leader if our log agrees with its log up to
+    /// `prev_log_index`.
+    pub(crate) fn on_append_entries(
+        &mut self,
+        term: u64,
+        leader_id: String,
+        prev_log_index: usize,
+        prev_log_term: u64,
+        entries: Vec<Entry>,
+        leader_commit: usize,
+    ) -> Payload {
+        self.maybe_step_down(term);
+
+        let reject = Payload::AppendEntriesRes {
+            term: self.term,
+            success: false,
+            match_index: 0,
+        };
+        if term < self.term {
+            return reject;
+        }
+
+        // a leader for our term exists, so whatever election we were
+        // running is lost
+        self.become_follower();
+        self.leader = Some(leader_id);
+        self.leader_contact = Some(self.clock.now());
+
+        // entries up to the start of our log are committed, so they agree
+        let agrees = prev_log_index < self.log.base()
+            || self
+                .log
+                .get(prev_log_index)
+                .is_some_and(|entry| entry.term == prev_log_term);
+        if !agrees {
+            return reject;
+        }
+
+        let match_index = prev_log_index + entries.len();
+        self.log.merge(prev_log_index + 1, entries);
+        self.refresh_membership();
+        // a stale request may know of fewer entries than we have committed
+        self.commit_index = self.commit_index.max(leader_commit.min(match_index));
+
+        Payload::AppendEntriesRes {
+            term: self.term,
+            success: true,
+            match_index,
+        }
+    }
+
+    pub(crate) fn on_append_entries_res(
+        &mut self,
+        src: String,
+        in_reply_to: Option<usize>,
+        term: u64,
+        success: bool,
+        match_index: usize,
+    ) {
+        self.maybe_step_down_for(&src, term);
+
+        if self.role != Role::Leader || term != self.term {
+            return;
+        }
+        self.heard_from.insert(src.clone(), self.clock.now());
+
+        // even a rejection means the follower still takes us for leader
+        let sent = in_reply_to.and_then(|msg_id| self.inflight.remove(&msg_id));
+        if let Some((_, sent_at)) = sent {
+            let acked_at = self.acked_at.entry(src.clone()).or_insert(sent_at);
+            *acked_at = (*acked_at).max(sent_at);
+        }
+
+        if success {
+            let matched = self.match_index.entry(src.clone()).or_default();
+            *matched = (*matched).max(match_index);
+            let next_index = self.next_index.entry(src).or_default();
+            *next_index = (*next_index).max(match_index + 1);
+        } else {
+            // walk back until our logs agree
+            let next_index = self.next_index.entry(src).or_default();
+            *next_index = next_index.saturating_sub(1).max(1);
+        }
+    }
+
+    /// Commits up to the highest index stored on a majority, as long as that
+    /// entry is from our term; earlier entries are committed along with it.
+    fn advance_commit_index(&mut self, node: &Node) {
+        let majority_index = self.membership.quorum_value(|voter| {
+            if voter == node.id {
+                Some(self.log.size())
+            } else {
+                self.match_index.get(voter).copied()
+            }
+        });
+        let Some(majority_index) = majority_index else {
+            return;
+        };
+
+        let from_our_term = self
+            .log
+            .get(majority_index)
+            .is_some_and(|entry| entry.term == self.term);
+        if self.commit_index < majority_index && from_our_term {
+            self.commit_index = majority_index;
+        }
+    }
+
+    /// Applies committed entries to `storage`, answering the requests this
+    /// node took in.
+    fn apply(&mut self, node: &Node) -> Vec<Message<Body>> {
+        let mut to_send = Vec::new();
+
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let Some(request) = self
+                .log
+                .get(self.last_applied)
+                .and_then(|entry| entry.op.clone())
+            else {
+                continue;
+            };
+
+            let reply = self.storage.apply(request.body.payload.clone());
+            if request.dest == node.id {
+                to_send.push(node.reply(&request, reply));
+            }
+        }
+
+        to_send
+    }
+
+    /// Compacts the log into a snapshot once enough applied entries have
+    /// piled up.
+    fn maybe_snapshot(&mut self) {
+        if self.last_applied < self.log.base() + self.config.snapshot_interval {
+            return;
+        }
+
+        let index = self.last_applied;
+        let membership = match self.log.membership(index) {
+            Some((_, membership)) => membership.clone(),
+            None => self.snapshot.membership.clone(),
+        };
+        self.snapshot = Snapshot {
+            last_included_index: index,
+            last_included_term: self.log.get(index).expect("applied entries exist").term,
+            data: self.storage.snapshot(),
+            membership,
+        };
+        self.log.compact(index);
+        self.refresh_membership();
+        // followers part way through the old snapshot start over
+        self.snapshot_sent.clear();
+    }
+
+    /// The next chunk of our snapshot that `follower` has not acknowledged.
+    fn send_snapshot(&mut self, node: &mut Node, follower: &str) -> Message<Body> {
+        let data = &self.snapshot.data;
+        let offset = self
+            .snapshot_sent
+            .get(follower)
+            .copied()
+            .unwrap_or_default()
+            .min(data.len());
+        let end = (offset + SNAPSHOT_CHUNK_SIZE).min(data.len());
+
+        let request = Payload::InstallSnapshot {
+            term: self.term,
+            leader_id: node.id.clone(),
+            last_included_index: self.snapshot.last_included_index,
+            last_included_term: self.snapshot.last_included_term,
+            offset,
+            data: hex::encode(&data[offset..end]),
+            done: end == data.len(),
+            membership: self.snapshot.membership.clone(),
+        };
+        node.request(follower, request)
+    }
+
+    /// Collects a snapshot from the leader chunk by chunk, and replaces our
+    /// state machine and log with it once it is complete.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn on_install_snapshot(
+        &mut self,
+        term: u64,
+        leader_id: String,
+        last_included_index: usize,
+        last_included_term: u64,
+        offset: usize,
+        data: String,
+        done: bool,
+        membership: Membership,
+    ) -> Payload {
+        self.maybe_step_down(term);
+
+        let current_term = self.term;
+        let reply = |offset, done| Payload::InstallSnapshotRes {
+            term: current_term,
+            last_included_index,
+            offset,
+            done,
+        };
+        if term < self.term {
+            return reply(0, false);
+        }
+
+        self.become_follower();
+        self.leader = Some(leader_id);
+        self.leader_contact = Some(self.clock.now());
+
+        // committed entries agree with the leader's, so a snapshot we are
+        // already past has nothing new
+        if last_included_index <= self.commit_index {
+            self.receiving = None;
+            return reply(offset, true);
+        }
+
+        let (index, received) = self
+            .receiving
+            .get_or_insert_with(|| (last_included_index, Vec::new()));
+        if *index != last_included_index {
+            *index = last_included_index;
+            received.clear();
+        }
+        // chunks must arrive in order; anything else tells the leader where
+        // to resume
+        if offset != received.len() {
+            return reply(received.len(), false);
+        }
+        let Ok(chunk) = hex::decode(&data) else {
+            return reply(received.len(), false);
+        };
+        received.extend(chunk);
+        if !done {
+            return reply(received.len(), false);
+        }
+
+        let (_, data) = self.receiving.take().expect("a snapshot is being received");
+        let Ok(storage) = Storage::restore(&data) else {
+            return reply(0, false);
+        };
+        self.storage = storage;
+        self.log.reset(last_included_index, last_included_term);
+        self.commit_index = last_included_index;
+        self.last_applied = last_included_index;
+        let received = data.len();
+        self.snapshot = Snapshot {
+            last_included_index,
+            last_included_term,
+            data,
+            membership,
+        };
+        self.refresh_membership();
+
+        reply(received, true)
+    }
+
+    pub(crate) fn on_install_snapshot_res(
+        &mut self,
+        src: String,
+        term: u64,
+        last_included_index: usize,
+        offset: usize,
+        done: bool,
+    ) {
+        self.maybe_step_down_for(&src, term);
+
+        if self.role != Role::Leader || term != self.term {
+            return;
+        }
+        self.heard_from.insert(src.clone(), self.clock.now());
+
+        if last_included_index != self.snapshot.last_included_index {
+            return;
+        }
+
+        // ready for the next chunk, or for entries
+        self.last_append.remove(&src);
+        if done {
+            self.snapshot_sent.remove(&src);
+            let matched = self.match_index.entry(src.clone()).or_default();
+            *matched = (*matched).max(last_included_index);
+            let next_index = self.next_index.entry(src).or_default();
+            *next_index = (*next_index).max(last_included_index + 1);
+        } else {
+            self.snapshot_sent.insert(src, offset);
+        }
+    }
+
+    /// Takes in a read on the leader, to be answered from the state machine
+    /// once it is known to be up to date.
+    fn read(&mut self, node: &Node, request: Message<Body>) -> Vec<Message<Body>> {
+        let now = self.clock.now();
+        let leased = self.config.read_mode == ReadMode::Lease
+            && self.lease_expiry(node).is_some_and(|expiry| now < expiry);
+
+        self.pending_reads.push(PendingRead {
+            request,
+            read_index: self.commit_index.max(self.term_start),
+            confirm_after: if leased { None } else { Some(now) },
+        });
+        self.serve_reads(node)
+    }
+
+    /// The latest time a majority, counting us, has acknowledged
+    /// `append_entries` sent at or after. We were leader until then at least.
+    fn confirmed_at(&self, node: &Node) -> Option<Instant> {
+        let now = self.clock.now();
+        self.membership.quorum_value(|voter| {
+            if voter == node.id {
+                Some(now)
+            } else {
+                self.acked_at.get(voter).copied()
+            }
+        })
+    }
+
+    /// Until when no other leader can be elected: followers that heard from
+    /// us refuse votes for an election timeout, less a margin for clock
+    /// drift.
+    fn lease_expiry(&self, node: &Node) -> Option<Instant> {
+        let lease = ELECTION_TIMEOUT.saturating_sub(self.config.lease_drift);
+        self.confirmed_at(node).map(|at| at + lease)
+    }
+
+    /// Answers the pending reads whose leadership check has passed and whose
+    /// read index has been applied. Reads are dropped with an error if we
+    /// lose leadership; they have no effect, so failing them is safe.
+    fn serve_reads(&mut self, node: &Node) -> Vec<Message<Body>> {
+        if self.pending_reads.is_empty() {
+            return vec![];
+        }
+
+        if self.role != Role::Leader {
+            let error = Payload::Error {
+                code: TEMPORARILY_UNAVAILABLE,
+                text: "leadership lost".to_string(),
+            };
+            return self
+                .pending_reads
+                .drain(..)
+                .map(|read| node.reply(&read.request, error.clone()))
+                .collect();
+        }
+
+        let confirmed_at = self.confirmed_at(node);
+        let last_applied = self.last_applied;
+        let (ready, waiting) = self.pending_reads.drain(..).partition(|read| {
+            let confirmed = read
+                .confirm_after
+                .is_none_or(|after| confirmed_at.is_some_and(|at| at >= after));
+            confirmed && read.read_index <= last_applied
+        });
+        self.pending_reads = waiting;
+
+        ready
+            .into_iter()
+            .map(|read: PendingRead| {
+                let reply = self.storage.apply(read.request.body.payload.clone());
+                node.reply(&read.request, reply)
+            })
+            .collect()
+    }
+
+    /// Re-reads the membership after the log changed: the latest change in
+    /// the log, or else the snapshot's.
+    fn refresh_membership(&mut self) {
+        (self.membership_index, self.membership) = match self.log.membership(self.log.size()) {
+            Some((index, membership)) => (index, membership.clone()),
+            None => (
+                self.snapshot.last_included_index,
+                self.snapshot.membership.clone(),
+            ),
+        };
+    }
+
+    /// Appends a membership change, which takes effect right away.
+    fn append_membership(&mut self, membership: Membership) {
+        let dropped: Vec<String> = self
+            .membership
+            .members()
+            .into_iter()
+            .filter(|member| !membership.contains(member))
+            .cloned()
+            .collect();
+
+        self.log.append(Entry {
+            term: self.term,
+            op: None,
+            membership: Some(membership),
+        });
+        self.refresh_membership();
+
+        for member in dropped {
+            self.departing.insert(member, self.membership_index);
+        }
+    }
+
+    /// Starts the change an `add_node` or `remove_node` asks for, to be
+    /// answered once it is committed. Changes to the voters go one at a
+    /// time: a new one waits until the last one is committed and an entry
+    /// of our term is too.
+    fn change_membership(&mut self, node: &Node, request: Message<Body>) -> Vec<Message<Body>> {
+        let mut membership = self.membership.clone();
+        match &request.body.payload {
+            Payload::AddNode { node: added } => {
+                if !membership.contains(added) {
+                    membership.learners.insert(added.clone());
+                }
+            }
+            Payload::RemoveNode { node: removed } => {
+                if membership.learners.remove(removed) {
+                    // learners count towards nothing, so they just go
+                } else if membership.voters.contains(removed) {
+                    if membership.voters.len() == 1 {
+                        let error = Payload::Error {
+                            code: PRECONDITION_FAILED,
+                            text: "can't remove the last voter".to_string(),
+                        };
+                        return vec![node.reply(&request, error)];
+                    }
+                    membership.old_voters = Some(membership.voters.clone());
+                    membership.voters.remove(removed);
+                }
+            }
+            _ => panic!("not a membership change"),
+        }
+
+        if membership != self.membership {
+            let committed = self.commit_index >= self.membership_index.max(self.term_start);
+            if !committed || self.membership.is_joint() {
+                let error = Payload::Error {
+                    code: TEMPORARILY_UNAVAILABLE,
+                    text: "membership change in progress".to_string(),
+                };
+                return vec![node.reply(&request, error)];
+            }
+            self.append_membership(membership);
+        }
+
+        self.pending_changes.push((request, self.clock.now()));
+        self.answer_changes(node)
+    }
+
+    /// Takes a membership change a step further once the last one is
+    /// committed: out of a joint configuration, or a learner that has
+    /// caught up into one that makes it a voter. A leader that is no longer
+    /// a voter steps down, and has the voter with the most of its log call
+    /// an election right away rather than leave the group without a leader
+    /// until someone times out.
+    fn advance_membership(&mut self, node: &mut Node) -> Vec<Message<Body>> {
+        if self.commit_index < self.membership_index {
+            return vec![];
+        }
+
+        let mut membership = self.membership.clone();
+        if membership.old_voters.take().is_some() {
+            self.append_membership(membership);
+            return vec![];
+        }
+
+        if !membership.voters.contains(&node.id) {
+            let successor = membership
+                .voters
+                .iter()
+                .max_by_key(|voter| self.match_index.get(*voter).copied().unwrap_or_default());
+            let timeout_now = successor
+                .map(|successor| node.request(successor, Payload::TimeoutNow { term: self.term }));
+            self.become_follower();
+            return timeout_now.into_iter().collect();
+        }
+
+        let caught_up = membership
+            .learners
+            .iter()
+            .find(|learner| {
+                self.match_index
+                    .get(*learner)
+                    .is_some_and(|matched| *matched >= self.commit_index)
+            })
+            .cloned();
+        if let Some(learner) = caught_up {
+            membership.learners.remove(&learner);
+            membership.old_voters = Some(membership.voters.clone());
+            membership.voters.insert(learner);
+            self.append_membership(membership);
+        }
+
+        vec![]
+    }
+
+    /// Answers the `add_node` and `remove_node` requests this node took in
+    /// once the membership they ask for is committed.
+    fn answer_changes(&mut self, node: &Node) -> Vec<Message<Body>> {
+        let now = self.clock.now();
+        self.pending_changes
+            .retain(|(_, arrived_at)| now.duration_since(*arrived_at) < PROXY_TIMEOUT);
+
+        let settled = self.commit_index >= self.membership_index && !self.membership.is_joint();
+        if self.pending_changes.is_empty() || !settled {
+            return vec![];
+        }
+
+        let membership = &self.membership;
+        let (done, waiting) =
+            self.pending_changes
+                .drain(..)
+                .partition(|(request, _)| match &request.body.payload {
+                    Payload::AddNode { node } => membership.voters.contains(node),
+                    Payload::RemoveNode { node } => !membership.contains(node),
+                    _ => panic!("not a membership change"),
+                });
+        self.pending_changes = waiting;
+
+        done.into_iter()
+            .map(|(request, _): (Message<Body>, Instant)| {
+                let reply = match request.body.payload {
+                    Payload::AddNode { .. } => Payload::AddNodeOk {},
+                    _ => Payload::RemoveNodeOk {},
+                };
+                node.reply(&request, reply)
+            })
+            .collect()
+    }
+
+    /// Starts handing leadership to the voter a `transfer_leadership` names,
+    /// or else to the one with the most of our log. Client requests are
+    /// turned away meanwhile, so the target can catch up for good.
+    fn transfer_leadership(
+        &mut self,
+        node: &mut Node,
+        request: Message<Body>,
+    ) -> Vec<Message<Body>> {
+        let Payload::TransferLeadership { node: target } = &request.body.payload else {
+            panic!("not a leadership transfer");
+        };
+        let target = target.clone().or_else(|| {
+            self.membership
+                .voters()
+                .into_iter()
+                .filter(|voter| **voter != node.id)
+                .max_by_key(|voter| self.match_index.get(*voter).copied().unwrap_or_default())
+                .cloned()
+        });
+
+        let error = match target {
+            Some(target) if target == node.id => {
+                return vec![node.reply(&request, Payload::TransferLeadershipOk {})];
+            }
+            Some(target) if self.membership.is_voter(&target) => {
+                self.transfer = Some(Transfer {
+                    request,
+                    target,
+                    deadline: self.clock.now() + ELECTION_TIMEOUT,
+                    timeout_sent: false,
+                });
+                return self.advance_transfer(node);
+            }
+            Some(target) => format!("{} is not a voter", target),
+            None => "no other voter".to_string(),
+        };
+        let error = Payload::Error {
+            code: PRECONDITION_FAILED,
+            text: error,
+        };
+        vec![node.reply(&request, error)]
+    }
+
+    /// Tells the transfer target to call an election once it has all of our
+    /// log, and answers the transfer once the target leads or time is up.
+    fn advance_transfer(&mut self, node: &mut Node) -> Vec<Message<Body>> {
+        let Some(transfer) = &mut self.transfer else {
+            return vec![];
+        };
+
+        if self.leader.as_ref() == Some(&transfer.target) {
+            let transfer = self.transfer.take().expect("a transfer is running");
+            return vec![node.reply(&transfer.request, Payload::TransferLeadershipOk {})];
+        }
+
+        if self.clock.now() >= transfer.deadline {
+            let transfer = self.transfer.take().expect("a transfer is running");
+            // followers may have voted for the target since acknowledging
+            // us, so those acknowledgements no longer hold up a lease
+            self.acked_at.clear();
+            self.inflight.clear();
+
+            let error = Payload::Error {
+                code: TEMPORARILY_UNAVAILABLE,
+                text: "leadership transfer timed out".to_string(),
+            };
+            return vec![node.reply(&transfer.request, error)];
+        }
+
+        let caught_up = self
+            .match_index
+            .get(&transfer.target)
+            .is_some_and(|matched| *matched >= self.log.size());
+        if self.role == Role::Leader && caught_up && !transfer.timeout_sent {
+            transfer.timeout_sent = true;
+            let timeout_now = Payload::TimeoutNow { term: self.term };
+            return vec![node.request(&transfer.target, timeout_now)];
+        }
+
+        vec![]
+    }
+
+    /// Calls an election right away, at the request of a leader handing
+    /// over to us.
+    pub(crate) fn on_timeout_now(&mut self, node: &mut Node, term: u64) -> Vec<Message<Body>> {
+        self.maybe_step_down(term);
+
+        if term != self.term || self.role == Role::Leader || !self.membership.is_voter(&node.id) {
+            return vec![];
+        }
+
+        self.become_candidate(node, true)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{collections::VecDeque, sync::Mutex};
+
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::{handle_message, TICK};
+
+    /// A clock that only moves when the test moves it.
+    #[derive(Debug, Clone)]
+    pub(crate) struct FakeClock(Arc<Mutex<Instant>>);
+
+    impl FakeClock {
+        pub(crate) fn new() -> Self {
+            FakeClock(Arc::new(Mutex::new(Instant::now())))
+        }
+
+        pub(crate) fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    /// Node `id` of a cluster of `ids`, as Maelstrom starts it.
+    pub(crate) fn init(id: &str, ids: &[String]) -> Node {
+        Node::default().init(Message {
+            src: "c0".to_string(),
+            dest: id.to_string(),
+            body: Body {
+                id: Some(0),
+                in_reply_to: None,
+                group: None,
+                payload: Payload::Init {
+                    node_id: id.to_string(),
+                    node_ids: ids.to_vec(),
+                },
+            },
+        })
+    }
+
+    /// A group of Raft nodes whose messages arrive as soon as they are sent,
+    /// unless a partition is in the way.
+    struct Cluster {
+        clock: FakeClock,
+        nodes: Vec<(Node, Raft)>,
+        /// Nodes cut off from the rest, though not from clients.
+        partitioned: HashSet<String>,
+        /// Messages to anyone outside the cluster.
+        replies: Vec<Message<Body>>,
+        /// Messages between nodes that got through.
+        delivered: Vec<Message<Body>>,
+        next_client_msg_id: usize,
+    }
+
+    impl Cluster {
+        fn new(size: usize, config: Config) -> Cluster {
+            Cluster::with_voters(size, size, config)
+        }
+
+        /// A cluster of `size` nodes where only the first `voters` are in
+        /// the group to begin with.
+        fn with_voters(size: usize, voters: usize, config: Config) -> Cluster {
+            let ids: Vec<String> = (0..size).map(|i| format!("n{}", i)).collect();
+            let clock = FakeClock::new();
+
+            let nodes = ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| {
+                    let raft = Raft::new(
+                        config.clone(),
+                        Membership::new(ids[..voters].iter().cloned()),
+                        Arc::new(clock.clone()),
+                        StdRng::seed_from_u64(i as u64),
+                    );
+                    (init(id, &ids), raft)
+                })
+                .collect();
+
+            Cluster {
+                clock,
+                nodes,
+                partitioned: HashSet::new(),
+                replies: Vec::new(),
+                delivered: Vec::new(),
+                next_client_msg_id: 0,
+            }
+        }
+
+        fn raft_mut(&mut self, id: &str) -> &mut Raft {
+            let (_, raft) = self
+                .nodes
+                .iter_mut()
+                .find(|(node, _)| node.id == id)
+                .expect("no such node");
+            raft
+        }
+
+        fn raft(&self, id: &str) -> &Raft {
+            let (_, raft) = self
+                .nodes
+                .iter()
+                .find(|(node, _)| node.id == id)
+                .expect("no such node");
+            raft
+        }
+
+        fn ids(&self) -> Vec<String> {
+            self.nodes.iter().map(|(node, _)| node.id.clone()).collect()
+        }
+
+        /// The leader of the latest term that has one, and that term.
+        fn leader(&self) -> (String, u64) {
+            self.nodes
+                .iter()
+                .filter(|(_, raft)| raft.role == Role::Leader)
+                .map(|(node, raft)| (node.id.clone(), raft.term))
+                .max_by_key(|(_, term)| *term)
+                .expect("no leader")
+        }
+
+        /// Cuts `nodes` off from everyone else, until `heal`.
+        fn partition(&mut self, nodes: &[&str]) {
+            self.partitioned = nodes.iter().map(|node| node.to_string()).collect();
+        }
+
+        fn heal(&mut self) {
+            self.partitioned.clear();
+        }
+
+        fn reachable(&self, message: &Message<Body>) -> bool {
+            let ids = self.ids();
+            if !ids.contains(&message.src) || !ids.contains(&message.dest) {
+                return true;
+            }
+            self.partitioned.contains(&message.src) == self.partitioned.contains(&message.dest)
+        }
+
+        /// Passes `messages` on, and whatever they lead to, until no more
+        /// are sent.
+        fn deliver(&mut self, messages: Vec<Message<Body>>) {
+            let mut queue = VecDeque::from(messages);
+            while let Some(message) = queue.pop_front() {
+                if !self.reachable(&message) {
+                    continue;
+                }
+
+                let from_node = self.ids().contains(&message.src);
+                let dest = self
+                    .nodes
+                    .iter_mut()
+                    .find(|(node, _)| node.id == message.dest);
+                match dest {
+                    Some((node, raft)) => {
+                        if from_node {
+                            self.delivered.push(message.clone());
+                        }
+                        queue.extend(handle_message(node, raft, message));
+                    }
+                    None => self.replies.push(message),
+                }
+            }
+        }
+
+        /// Sends `payload` to `dest` from a client and returns its msg_id.
+        fn request(&mut self, dest: &str, payload: Payload) -> usize {
+            self.next_client_msg_id += 1;
+            let msg_id = self.next_client_msg_id;
+            self.deliver(vec![Message {
+                src: "c1".to_string(),
+                dest: dest.to_string(),
+                body: Body {
+                    id: Some(msg_id),
+                    in_reply_to: None,
+                    group: None,
+                    payload,
+                },
+            }]);
+            msg_id
+        }
+
+        /// Sends `payload` to `dest` from a client and waits a second for
+        /// the reply.
+        fn call(&mut self, dest: &str, payload: Payload) -> Option<Payload> {
+            let msg_id = self.request(dest, payload);
+            self.run_for(Duration::from_secs(1));
+            self.reply(msg_id).cloned()
+        }
+
+        /// What the client got back for its request `msg_id`, if anything.
+        fn reply(&self, msg_id: usize) -> Option<&Payload> {
+            self.replies
+                .iter()
+                .find(|reply| reply.body.in_reply_to == Some(msg_id))
+                .map(|reply| &reply.body.payload)
+        }
+
+        /// Moves the clock on by `duration`, one tick at a time.
+        fn run_for(&mut self, duration: Duration) {
+            let mut elapsed = Duration::ZERO;
+            while elapsed < duration {
+                self.clock.advance(TICK);
+                elapsed += TICK;
+
+                let mut messages = Vec::new();
+                for (node, raft) in &mut self.nodes {
+                    messages.extend(raft.tick(node));
+                }
+                self.deliver(messages);
+            }
+        }
+    }
+
+    /// A three node cluster that has elected a leader.
+    fn elected(config: Config) -> Cluster {
+        let mut cluster = Cluster::new(3, config);
+        cluster.run_for(Duration::from_secs(10));
+        cluster
+    }
+
+    /// Cuts a follower off and lets it back in, again and again.
+    fn flap_follower(cluster: &mut Cluster) {
+        let (leader, _) = cluster.leader();
+        let follower = cluster.ids().into_iter().find(|id| *id != leader).unwrap();
+
+        for _ in 0..5 {
+            cluster.partition(&[&follower]);
+            cluster.run_for(Duration::from_secs(5));
+            cluster.heal();
+            cluster.run_for(Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn pre_vote_keeps_a_flapping_follower_from_deposing_the_leader() {
+        let mut cluster = elected(Config {
+            pre_vote: true,
+            ..Config::default()
+        });
+        let (leader, term) = cluster.leader();
+
+        flap_follower(&mut cluster);
+
+        assert_eq!(cluster.leader(), (leader, term));
+        for id in cluster.ids() {
+            assert_eq!(cluster.raft(&id).term, term, "term of {}", id);
+        }
+    }
+
+    #[test]
+    fn without_pre_vote_a_flapping_follower_pushes_the_term_up() {
+        let mut cluster = elected(Config::default());
+        let (_, term) = cluster.leader();
+
+        flap_follower(&mut cluster);
+        // the last return may have left an election running
+        cluster.run_for(Duration::from_secs(10));
+
+        assert!(cluster.leader().1 > term);
+    }
+
+    #[test]
+    fn check_quorum_steps_an_isolated_leader_down() {
+        let mut cluster = elected(Config {
+            check_quorum: true,
+            ..Config::default()
+        });
+        let (leader, term) = cluster.leader();
+
+        cluster.partition(&[&leader]);
+        cluster.run_for(Duration::from_secs(5));
+
+        assert_ne!(cluster.raft(&leader).role, Role::Leader);
+        let (successor, successor_term) = cluster.leader();
+        assert_ne!(successor, leader);
+        assert!(successor_term > term);
+    }
+
+    #[test]
+    fn without_check_quorum_an_isolated_leader_keeps_leading() {
+        let mut cluster = elected(Config::default());
+        let (leader, _) = cluster.leader();
+
+        cluster.partition(&[&leader]);
+        cluster.run_for(Duration::from_secs(5));
+
+        assert_eq!(cluster.raft(&leader).role, Role::Leader);
+    }
+
+    fn write(cluster: &mut Cluster, value: u64) {
+        let (leader, _) = cluster.leader();
+        let write = Payload::Write {
+            key: serde_json::json!("k"),
+            value: serde_json::json!(value),
+        };
+        let msg_id = cluster.request(&leader, write);
+        cluster.run_for(Duration::from_secs(1));
+        assert!(matches!(cluster.reply(msg_id), Some(Payload::WriteOk {})));
+    }
+
+    fn read() -> Payload {
+        Payload::Read {
+            key: serde_json::json!("k"),
+        }
+    }
+
+    fn read_ok(reply: Option<&Payload>) -> bool {
+        matches!(reply, Some(Payload::ReadOk { .. }))
+    }
+
+    #[test]
+    fn read_index_serves_the_latest_write() {
+        let mut cluster = elected(Config::default());
+        write(&mut cluster, 1);
+        write(&mut cluster, 2);
+
+        let (leader, _) = cluster.leader();
+        let msg_id = cluster.request(&leader, read());
+        cluster.run_for(Duration::from_secs(1));
+
+        assert!(matches!(
+            cluster.reply(msg_id),
+            Some(Payload::ReadOk { value }) if *value == serde_json::json!(2)
+        ));
+    }
+
+    #[test]
+    fn read_index_keeps_a_deposed_leader_from_serving_reads() {
+        let mut cluster = elected(Config::default());
+        write(&mut cluster, 1);
+        let (leader, _) = cluster.leader();
+
+        cluster.partition(&[&leader]);
+        cluster.run_for(Duration::from_secs(5));
+        write(&mut cluster, 2);
+
+        // without check_quorum the old leader still thinks it leads
+        assert_eq!(cluster.raft(&leader).role, Role::Leader);
+        let msg_id = cluster.request(&leader, read());
+        cluster.run_for(Duration::from_secs(5));
+
+        assert!(!read_ok(cluster.reply(msg_id)));
+    }
+
+    #[test]
+    fn lease_runs_out_before_anyone_else_can_be_elected() {
+        let mut cluster = elected(Config {
+            read_mode: ReadMode::Lease,
+            ..Config::default()
+        });
+        write(&mut cluster, 1);
+        let (leader, term) = cluster.leader();
+
+        cluster.partition(&[&leader]);
+        let mut last_served = None;
+        let mut successor_elected = None;
+        for step in 0..100 {
+            let msg_id = cluster.request(&leader, read());
+            if read_ok(cluster.reply(msg_id)) {
+                last_served = Some(step);
+            }
+            if successor_elected.is_none() && cluster.leader().1 > term {
+                successor_elected = Some(step);
+            }
+            cluster.run_for(Duration::from_millis(100));
+        }
+
+        let last_served = last_served.expect("the lease served no reads");
+        let successor_elected = successor_elected.expect("no successor was elected");
+        assert!(last_served < successor_elected);
+    }
+
+    fn followers(cluster: &Cluster) -> Vec<String> {
+        let (leader, _) = cluster.leader();
+        cluster
+            .ids()
+            .into_iter()
+            .filter(|id| cluster.raft(id).membership().is_voter(id) && *id != leader)
+            .collect()
+    }
+
+    fn error_code(reply: Option<&Payload>) -> Option<u64> {
+        match reply {
+            Some(Payload::Error { code, .. }) => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Every membership in `raft`'s log, oldest first.
+    fn memberships(raft: &Raft) -> Vec<Membership> {
+        (raft.log.base()..=raft.log.size())
+            .filter_map(|index| raft.log.get(index)?.membership.clone())
+            .collect()
+    }
+
+    fn remove(cluster: &mut Cluster, node: &str) -> usize {
+        let (leader, _) = cluster.leader();
+        let remove = Payload::RemoveNode {
+            node: node.to_string(),
+        };
+        cluster.request(&leader, remove)
+    }
+
+    #[test]
+    fn added_node_stays_a_learner_until_it_catches_up() {
+        let mut cluster = Cluster::with_voters(4, 3, Config::default());
+        cluster.run_for(Duration::from_secs(10));
+        write(&mut cluster, 1);
+        let (leader, _) = cluster.leader();
+
+        cluster.partition(&["n3"]);
+        let add = Payload::AddNode {
+            node: "n3".to_string(),
+        };
+        let msg_id = cluster.request(&leader, add);
+        cluster.run_for(Duration::from_secs(2));
+
+        let membership = cluster.raft(&leader).membership();
+        assert!(membership.learners.contains("n3"));
+        assert!(!membership.is_voter("n3"));
+        assert!(cluster.reply(msg_id).is_none());
+
+        cluster.heal();
+        cluster.run_for(Duration::from_secs(2));
+
+        assert!(matches!(cluster.reply(msg_id), Some(Payload::AddNodeOk {})));
+        let history = memberships(cluster.raft(&leader));
+        let joint = history
+            .iter()
+            .position(|membership| membership.is_joint() && membership.voters.contains("n3"))
+            .expect("no joint configuration");
+        let last = history.last().unwrap();
+        assert!(joint < history.len() - 1);
+        assert!(!last.is_joint() && last.voters.contains("n3"));
+        assert_eq!(cluster.raft("n3").membership(), last);
+    }
+
+    #[test]
+    fn removed_follower_learns_it_was_removed() {
+        let mut cluster = elected(Config::default());
+        let (leader, _) = cluster.leader();
+        let follower = followers(&cluster).remove(0);
+
+        let msg_id = remove(&mut cluster, &follower);
+        cluster.run_for(Duration::from_secs(1));
+
+        assert!(matches!(
+            cluster.reply(msg_id),
+            Some(Payload::RemoveNodeOk {})
+        ));
+        assert!(!cluster.raft(&leader).membership().contains(&follower));
+        assert!(!cluster.raft(&follower).membership().contains(&follower));
+    }
+
+    #[test]
+    fn removed_node_does_not_disrupt_the_leader() {
+        let mut cluster = elected(Config::default());
+        let (leader, term) = cluster.leader();
+        let follower = followers(&cluster).remove(0);
+
+        remove(&mut cluster, &follower);
+        cluster.run_for(Duration::from_secs(30));
+
+        assert_eq!(cluster.leader(), (leader, term));
+        assert_eq!(cluster.raft(&follower).term, term);
+    }
+
+    #[test]
+    fn removed_leader_hands_over_to_a_successor() {
+        let mut cluster = elected(Config::default());
+        let (leader, term) = cluster.leader();
+
+        let msg_id = remove(&mut cluster, &leader);
+        // well within the shortest election timeout, so only timeout_now
+        // can have started the election
+        cluster.run_for(Duration::from_secs(1));
+
+        assert!(matches!(
+            cluster.reply(msg_id),
+            Some(Payload::RemoveNodeOk {})
+        ));
+        let (successor, successor_term) = cluster.leader();
+        assert_ne!(successor, leader);
+        assert_eq!(successor_term, term + 1);
+        assert!(!cluster.raft(&successor).membership().contains(&leader));
+    }
+
+    #[test]
+    fn second_change_is_turned_away_while_one_is_in_flight() {
+        let mut cluster = elected(Config::default());
+        let followers = followers(&cluster);
+
+        let first = remove(&mut cluster, &followers[0]);
+        let second = remove(&mut cluster, &followers[1]);
+        assert_eq!(
+            error_code(cluster.reply(second)),
+            Some(TEMPORARILY_UNAVAILABLE)
+        );
+
+        cluster.run_for(Duration::from_secs(1));
+        assert!(matches!(
+            cluster.reply(first),
+            Some(Payload::RemoveNodeOk {})
+        ));
+    }
+
+    #[test]
+    fn node_removed_while_cut_off_does_not_disrupt_the_leader_once_back() {
+        let mut cluster = Cluster::new(5, Config::default());
+        cluster.run_for(Duration::from_secs(10));
+        let cut_off = followers(&cluster).remove(0);
+
+        cluster.partition(&[&cut_off]);
+        let msg_id = remove(&mut cluster, &cut_off);
+        cluster.run_for(Duration::from_secs(2));
+        assert!(matches!(
+            cluster.reply(msg_id),
+            Some(Payload::RemoveNodeOk {})
+        ));
+
+        // long enough for it to call elections from the old configuration
+        cluster.run_for(Duration::from_secs(10));
+        assert!(cluster.raft(&cut_off).term > cluster.leader().1);
+
+        let leader = cluster.leader();
+        cluster.heal();
+        cluster.run_for(Duration::from_secs(30));
+        assert_eq!(cluster.leader(), leader);
+    }
+
+    #[test]
+    fn node_removed_while_cut_off_learns_it_once_back_with_pre_vote() {
+        let mut cluster = Cluster::new(
+            5,
+            Config {
+                pre_vote: true,
+                ..Config::default()
+            },
+        );
+        cluster.run_for(Duration::from_secs(10));
+        let cut_off = followers(&cluster).remove(0);
+
+        cluster.partition(&[&cut_off]);
+        remove(&mut cluster, &cut_off);
+        cluster.run_for(Duration::from_secs(10));
+        cluster.heal();
+        cluster.run_for(Duration::from_secs(2));
+
+        assert!(!cluster.raft(&cut_off).membership().contains(&cut_off));
+    }
+
+    #[test]
+    fn change_on_a_leader_cut_off_from_the_majority_is_rolled_back() {
+        let mut cluster = Cluster::new(5, Config::default());
+        cluster.run_for(Duration::from_secs(10));
+        let (leader, _) = cluster.leader();
+        let followers = followers(&cluster);
+
+        cluster.partition(&[&leader, &followers[0]]);
+        let msg_id = remove(&mut cluster, &followers[1]);
+        cluster.run_for(Duration::from_secs(10));
+        assert!(cluster.reply(msg_id).is_none());
+
+        cluster.heal();
+        cluster.run_for(Duration::from_secs(5));
+
+        assert_ne!(cluster.raft(&leader).role, Role::Leader);
+        for id in cluster.ids() {
+            assert_eq!(
+                cluster.raft(&id).membership().voters.len(),
+                5,
+                "voters of {}",
+                id
+            );
+        }
+    }
+
+    #[test]
+    fn kv_requests_fail_with_maelstrom_error_codes() {
+        let mut cluster = elected(Config::default());
+        let (leader, _) = cluster.leader();
+        let key = serde_json::json!("k");
+        let cas = |from: u64, to: u64, create_if_not_exists: bool| Payload::Cas {
+            key: key.clone(),
+            from: serde_json::json!(from),
+            to: serde_json::json!(to),
+            create_if_not_exists,
+        };
+
+        let missing = cluster.call(&leader, read());
+        assert_eq!(error_code(missing.as_ref()), Some(20));
+        assert_eq!(
+            error_code(cluster.call(&leader, cas(0, 1, false)).as_ref()),
+            Some(20)
+        );
+
+        let created = cluster.call(&leader, cas(0, 1, true));
+        assert!(matches!(created, Some(Payload::CasOk {})));
+        let stale = cluster.call(&leader, cas(0, 2, false));
+        assert_eq!(error_code(stale.as_ref()), Some(PRECONDITION_FAILED));
+        assert!(matches!(
+            cluster.call(&leader, cas(1, 2, false)),
+            Some(Payload::CasOk {})
+        ));
+
+        let write = Payload::Write {
+            key: key.clone(),
+            value: serde_json::json!(3),
+        };
+        assert!(matches!(
+            cluster.call(&leader, write),
+            Some(Payload::WriteOk {})
+        ));
+        assert!(matches!(
+            cluster.call(&leader, read()),
+            Some(Payload::ReadOk { value }) if value == serde_json::json!(3)
+        ));
+    }
+
+    #[test]
+    fn deposed_leader_drops_the_entries_it_could_not_commit() {
+        let mut cluster = elected(Config::default());
+        write(&mut cluster, 1);
+        let (leader, _) = cluster.leader();
+
+        cluster.partition(&[&leader]);
+        let write_to_old = Payload::Write {
+            key: serde_json::json!("k"),
+            value: serde_json::json!(2),
+        };
+        let lost = cluster.request(&leader, write_to_old);
+        cluster.run_for(Duration::from_secs(5));
+        write(&mut cluster, 3);
+        write(&mut cluster, 4);
+
+        cluster.heal();
+        cluster.run_for(Duration::from_secs(2));
+
+        assert!(!matches!(cluster.reply(lost), Some(Payload::WriteOk {})));
+        let (successor, _) = cluster.leader();
+        let (old, new) = (&cluster.raft(&leader).log, &cluster.raft(&successor).log);
+        assert_eq!(old.size(), new.size());
+        for index in new.base()..=new.size() {
+            let term = |log: &Log| log.get(index).map(|entry| entry.term);
+            assert_eq!(term(old), term(new), "term at {}", index);
+        }
+    }
+
+    #[test]
+    fn entries_from_earlier_terms_commit_only_along_with_one_of_ours() {
+        let ids: Vec<String> = (0..3).map(|i| format!("n{}", i)).collect();
+        let node = init("n0", &ids);
+        let mut raft = Raft::new(
+            Config::default(),
+            Membership::new(ids.clone()),
+            Arc::new(SystemClock),
+            StdRng::seed_from_u64(0),
+        );
+        let entry = |term| Entry {
+            term,
+            op: None,
+            membership: None,
+        };
+
+        // an entry of term 2 reached a majority, but its leader was lost
+        // before committing it
+        raft.log.append(entry(2));
+        raft.term = 3;
+        raft.role = Role::Leader;
+        raft.match_index.insert("n1".to_string(), 2);
+        raft.advance_commit_index(&node);
+        assert_eq!(raft.commit_index, 0);
+
+        raft.log.append(entry(3));
+        raft.match_index.insert("n1".to_string(), 3);
+        raft.advance_commit_index(&node);
+        assert_eq!(raft.commit_index, 3);
+    }
+
+    #[test]
+    fn follower_passes_requests_on_and_relays_the_reply() {
+        let mut cluster = elected(Config::default());
+        let follower = followers(&cluster).remove(0);
+
+        let write = Payload::Write {
+            key: serde_json::json!("k"),
+            value: serde_json::json!(1),
+        };
+        let msg_id = cluster.request(&follower, write);
+        cluster.run_for(Duration::from_secs(1));
+
+        let reply = cluster
+            .replies
+            .iter()
+            .find(|reply| reply.body.in_reply_to == Some(msg_id))
+            .expect("no reply");
+        assert_eq!(reply.src, follower);
+        assert_eq!(reply.dest, "c1");
+        assert!(matches!(reply.body.payload, Payload::WriteOk {}));
+    }
+
+    #[test]
+    fn requests_are_turned_away_while_no_leader_is_known() {
+        // no time has passed, so nobody has called an election yet
+        let mut cluster = Cluster::new(3, Config::default());
+
+        let msg_id = cluster.request("n0", read());
+
+        assert_eq!(
+            error_code(cluster.reply(msg_id)),
+            Some(TEMPORARILY_UNAVAILABLE)
+        );
+    }
+
+    #[test]
+    fn lagging_follower_catches_up_from_a_chunked_snapshot() {
+        // pre-votes keep the follower from running up its term while cut
+        // off, which would depose the leader once it is back
+        let mut cluster = elected(Config {
+            snapshot_interval: 10,
+            pre_vote: true,
+            ..Config::default()
+        });
+        let (leader, _) = cluster.leader();
+        let follower = followers(&cluster).remove(0);
+
+        cluster.partition(&[&follower]);
+        // enough data that the snapshot takes several chunks
+        let value = serde_json::json!("x".repeat(SNAPSHOT_CHUNK_SIZE / 4));
+        for key in 0..20 {
+            let write = Payload::Write {
+                key: serde_json::json!(key),
+                value: value.clone(),
+            };
+            assert!(matches!(
+                cluster.call(&leader, write),
+                Some(Payload::WriteOk {})
+            ));
+        }
+        assert!(cluster.raft(&leader).log.base() > cluster.raft(&follower).log.size());
+
+        cluster.heal();
+        cluster.run_for(Duration::from_secs(3));
+
+        let offsets: HashSet<usize> = cluster
+            .delivered
+            .iter()
+            .filter(|message| message.dest == follower)
+            .filter_map(|message| match message.body.payload {
+                Payload::InstallSnapshot { offset, .. } => Some(offset),
+                _ => None,
+            })
+            .collect();
+        assert!(offsets.len() > 1, "sent in {} chunks", offsets.len());
+
+        let snapshot_index = cluster.raft(&leader).snapshot.last_included_index;
+        let caught_up = cluster.raft(&follower);
+        assert_eq!(caught_up.log.base(), snapshot_index);
+        assert_eq!(caught_up.last_applied, cluster.raft(&leader).last_applied);
+        let read = Payload::Read {
+            key: serde_json::json!(19),
+        };
+        assert!(matches!(
+            cluster.raft_mut(&follower).storage.apply(read),
+            Payload::ReadOk { value: read } if read == value
+        ));
+    }
+}
@@ -0,0 +1,411 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    sync::Arc,
+    time::Instant,
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{
+    config::Config,
+    handle_message,
+    membership::Membership,
+    node::Node,
+    raft::{Clock, Raft, SystemClock, PROXY_TIMEOUT, TEMPORARILY_UNAVAILABLE},
+    Body, Key, Message, Payload,
+};
+
+const MALFORMED_REQUEST: u64 = 12;
+
+/// The key space split across Raft groups. Each key belongs to one group by
+/// hash, and each group runs on its own subset of the nodes, so groups
+/// commit independently and throughput grows with the cluster.
+///
+/// A node runs a `Raft` for each group it is a member of. Client requests
+/// for any other group are passed on to one of that group's members, which
+/// takes them to its leader.
+#[derive(Debug)]
+pub(crate) struct Shards {
+    config: Config,
+    /// The voters of each group: where the groups were placed at init, kept
+    /// up to date for the groups we run.
+    map: Vec<Vec<String>>,
+    groups: BTreeMap<usize, Raft>,
+    /// Client requests passed on to another group's member, by the msg_id
+    /// they were sent under, with when they were sent.
+    forwarded: HashMap<usize, (Message<Body>, Instant)>,
+    /// Shared with every group's `Raft`.
+    clock: Arc<dyn Clock>,
+    /// Seeds each group's `Raft` and picks the members requests are passed
+    /// on to.
+    rng: StdRng,
+}
+
+impl Shards {
+    /// Places group `g` on the `group_size` nodes from the `g`th one on, so
+    /// the groups spread evenly over the initial cluster.
+    pub(crate) fn new(node: &Node, config: Config) -> Self {
+        Shards::with_clock(node, config, Arc::new(SystemClock), StdRng::from_entropy())
+    }
+
+    /// Groups reading the time from `clock` and drawing their randomness
+    /// from `rng`.
+    pub(crate) fn with_clock(
+        node: &Node,
+        config: Config,
+        clock: Arc<dyn Clock>,
+        rng: StdRng,
+    ) -> Self {
+        let initial_voters = config.initial_voters.unwrap_or(node.node_ids.len());
+        let cluster = &node.node_ids[..initial_voters.min(node.node_ids.len())];
+        let group_size = config
+            .group_size
+            .unwrap_or(cluster.len())
+            .min(cluster.len());
+        let map: Vec<Vec<String>> = (0..config.groups)
+            .map(|group| {
+                (0..group_size)
+                    .map(|i| cluster[(group + i) % cluster.len()].clone())
+                    .collect()
+            })
+            .collect();
+
+        let mut shards = Shards {
+            config,
+            map,
+            groups: BTreeMap::new(),
+            forwarded: HashMap::new(),
+            clock,
+            rng,
+        };
+        for group in 0..shards.map.len() {
+            if shards.map[group].contains(&node.id) {
+                shards.raft(group);
+            }
+        }
+        shards
+    }
+
+    /// The `Raft` for `group`, started on first use: a node being added to
+    /// a group starts running it when the group's leader first reaches it.
+    fn raft(&mut self, group: usize) -> &mut Raft {
+        self.groups.entry(group).or_insert_with(|| {
+            let membership = Membership::new(self.map[group].iter().cloned());
+            let rng = StdRng::seed_from_u64(self.rng.gen());
+            Raft::new(self.config.clone(), membership, self.clock.clone(), rng)
+        })
+    }
+
+    /// The group a key belongs to. Every node runs the same build, so they
+    /// all hash alike.
+    fn group_of(&self, key: &Key) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.to_string().hash(&mut hasher);
+        (hasher.finish() % self.map.len() as u64) as usize
+    }
+
+    pub(crate) fn tick(&mut self, node: &mut Node) -> Vec<Message<Body>> {
+        let mut to_send = Vec::new();
+
+        // the member we passed a request on to may be down or cut off; the
+        // client can try again rather than wait out its own timeout
+        let now = self.clock.now();
+        self.forwarded.retain(|_, (request, sent_at)| {
+            if now.duration_since(*sent_at) < PROXY_TIMEOUT {
+                return true;
+            }
+            let error = Payload::Error {
+                code: TEMPORARILY_UNAVAILABLE,
+                text: "no reply from the group".to_string(),
+            };
+            to_send.push(node.reply(request, error));
+            false
+        });
+
+        for (group, raft) in &mut self.groups {
+            let messages = raft.tick(node);
+            to_send.extend(tag(node, *group, messages));
+            self.map[*group] = raft.membership().voters.iter().cloned().collect();
+        }
+        to_send
+    }
+
+    /// Routes a message to the `Raft` of its group, or a client request for
+    /// a group we are not a member of to one that is.
+    pub(crate) fn handle(&mut self, node: &mut Node, input: Message<Body>) -> Vec<Message<Body>> {
+        let forwarded = input
+            .body
+            .in_reply_to
+            .and_then(|msg_id| self.forwarded.remove(&msg_id));
+        if let Some((request, _)) = forwarded {
+            return vec![node.reply(&request, input.body.payload)];
+        }
+
+        let key = match &input.body.payload {
+            Payload::Read { key } | Payload::Write { key, .. } | Payload::Cas { key, .. } => {
+                Some(key)
+            }
+            _ => None,
+        };
+        let group = input
+            .body
+            .group
+            .or_else(|| key.map(|key| self.group_of(key)))
+            .unwrap_or_default();
+        if group >= self.map.len() {
+            let error = Payload::Error {
+                code: MALFORMED_REQUEST,
+                text: format!("no group {}", group),
+            };
+            return vec![node.reply(&input, error)];
+        }
+
+        let from_node = node.node_ids.contains(&input.src);
+        let member = self
+            .groups
+            .get(&group)
+            .is_some_and(|raft| raft.membership().contains(&node.id));
+        if !from_node && !member {
+            return self.forward(node, group, input);
+        }
+
+        let to_send = handle_message(node, self.raft(group), input);
+        tag(node, group, to_send)
+    }
+
+    /// Passes a client request on to a member of the group it is for.
+    fn forward(
+        &mut self,
+        node: &mut Node,
+        group: usize,
+        request: Message<Body>,
+    ) -> Vec<Message<Body>> {
+        let member = self.map[group]
+            .choose(&mut self.rng)
+            .expect("groups have voters");
+        let mut message = node.request(member, request.body.payload.clone());
+        message.body.group = Some(group);
+
+        let msg_id = message.body.id.expect("requests always have a msg_id");
+        self.forwarded.insert(msg_id, (request, self.clock.now()));
+        vec![message]
+    }
+}
+
+/// Marks the messages a group sends to other nodes with the group; clients
+/// get plain replies.
+fn tag(node: &Node, group: usize, mut messages: Vec<Message<Body>>) -> Vec<Message<Body>> {
+    for message in &mut messages {
+        if node.node_ids.contains(&message.dest) {
+            message.body.group = Some(group);
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, time::Duration};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{
+        raft::tests::{init, FakeClock},
+        TICK,
+    };
+
+    /// Nodes running sharded groups, whose messages arrive as soon as they
+    /// are sent unless a node is down.
+    struct Network {
+        clock: FakeClock,
+        nodes: Vec<(Node, Shards)>,
+        /// Nodes that neither get nor send anything.
+        down: Vec<String>,
+        /// Messages to anyone outside the cluster.
+        replies: Vec<Message<Body>>,
+        next_client_msg_id: usize,
+    }
+
+    impl Network {
+        /// `size` nodes with two groups of two: n0 and n1 run group 0, n1
+        /// and n2 group 1, and n3 runs neither.
+        fn new(size: usize) -> Self {
+            let clock = FakeClock::new();
+            let ids: Vec<String> = (0..size).map(|i| format!("n{}", i)).collect();
+            let config = Config {
+                groups: 2,
+                group_size: Some(2),
+                ..Config::default()
+            };
+            let nodes = ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| {
+                    let node = init(id, &ids);
+                    let shards = Shards::with_clock(
+                        &node,
+                        config.clone(),
+                        Arc::new(clock.clone()),
+                        StdRng::seed_from_u64(i as u64),
+                    );
+                    (node, shards)
+                })
+                .collect();
+            Network {
+                clock,
+                nodes,
+                down: Vec::new(),
+                replies: Vec::new(),
+                next_client_msg_id: 0,
+            }
+        }
+
+        fn shards(&self, id: &str) -> &Shards {
+            &self.nodes.iter().find(|(node, _)| node.id == id).unwrap().1
+        }
+
+        fn deliver(&mut self, messages: Vec<Message<Body>>) {
+            let mut queue = VecDeque::from(messages);
+            while let Some(message) = queue.pop_front() {
+                if self.down.contains(&message.src) || self.down.contains(&message.dest) {
+                    continue;
+                }
+                let dest = self
+                    .nodes
+                    .iter_mut()
+                    .find(|(node, _)| node.id == message.dest);
+                match dest {
+                    Some((node, shards)) => queue.extend(shards.handle(node, message)),
+                    None => self.replies.push(message),
+                }
+            }
+        }
+
+        /// Sends `payload` to `dest` from a client, returning its msg_id.
+        fn request(&mut self, dest: &str, payload: Payload) -> usize {
+            self.next_client_msg_id += 1;
+            let msg_id = self.next_client_msg_id;
+            self.deliver(vec![Message {
+                src: "c1".to_string(),
+                dest: dest.to_string(),
+                body: Body {
+                    id: Some(msg_id),
+                    in_reply_to: None,
+                    group: None,
+                    payload,
+                },
+            }]);
+            msg_id
+        }
+
+        /// What the client got back for its request `msg_id`, if anything.
+        fn reply(&self, msg_id: usize) -> Option<&Message<Body>> {
+            self.replies
+                .iter()
+                .find(|reply| reply.body.in_reply_to == Some(msg_id))
+        }
+
+        /// Moves the clock on by `duration`, one tick at a time.
+        fn run_for(&mut self, duration: Duration) {
+            let mut elapsed = Duration::ZERO;
+            while elapsed < duration {
+                self.clock.advance(TICK);
+                elapsed += TICK;
+
+                let mut messages = Vec::new();
+                for (node, shards) in &mut self.nodes {
+                    messages.extend(shards.tick(node));
+                }
+                self.deliver(messages);
+            }
+        }
+    }
+
+    /// A key `group_of` puts in `group`.
+    fn key_in(shards: &Shards, group: usize) -> Key {
+        (0..)
+            .map(|i| json!(i))
+            .find(|key| shards.group_of(key) == group)
+            .unwrap()
+    }
+
+    fn write(key: &Key) -> Payload {
+        Payload::Write {
+            key: key.clone(),
+            value: json!("v"),
+        }
+    }
+
+    /// Whether the log of `raft` holds a write to `key`.
+    fn logged(raft: &Raft, key: &Key) -> bool {
+        raft.log
+            .entries_from(raft.log.base())
+            .iter()
+            .filter_map(|entry| entry.op.as_ref())
+            .any(|op| matches!(&op.body.payload, Payload::Write { key: written, .. } if written == key))
+    }
+
+    #[test]
+    fn writes_go_to_the_group_their_key_hashes_to() {
+        let mut network = Network::new(4);
+        network.run_for(Duration::from_secs(10));
+
+        for group in 0..2 {
+            let key = key_in(network.shards("n0"), group);
+            let msg_id = network.request("n1", write(&key));
+            network.run_for(Duration::from_secs(1));
+            assert!(matches!(
+                network.reply(msg_id).map(|reply| &reply.body.payload),
+                Some(Payload::WriteOk {})
+            ));
+
+            // n1 runs both groups
+            let groups = &network.shards("n1").groups;
+            assert!(logged(&groups[&group], &key));
+            assert!(!logged(&groups[&(1 - group)], &key));
+        }
+    }
+
+    #[test]
+    fn request_passed_on_to_another_group_gets_its_reply() {
+        let mut network = Network::new(4);
+        network.run_for(Duration::from_secs(10));
+
+        // n3 runs no group, so it has to pass the request on
+        let key = key_in(network.shards("n3"), 1);
+        assert!(network.shards("n3").groups.is_empty());
+        let msg_id = network.request("n3", write(&key));
+        network.run_for(Duration::from_secs(1));
+
+        let reply = network.reply(msg_id).expect("the client got a reply");
+        assert_eq!(reply.src, "n3");
+        assert_eq!(reply.dest, "c1");
+        assert!(matches!(reply.body.payload, Payload::WriteOk {}));
+        assert!(network.shards("n3").forwarded.is_empty());
+    }
+
+    #[test]
+    fn request_passed_on_to_an_unreachable_group_is_turned_away() {
+        let mut network = Network::new(4);
+        network.run_for(Duration::from_secs(10));
+
+        network.down = vec!["n1".to_string(), "n2".to_string()];
+        let key = key_in(network.shards("n3"), 1);
+        let msg_id = network.request("n3", write(&key));
+        network.run_for(PROXY_TIMEOUT - TICK);
+        assert!(network.reply(msg_id).is_none());
+
+        network.run_for(TICK * 2);
+        let reply = network.reply(msg_id).expect("the client got an answer");
+        assert!(matches!(
+            reply.body.payload,
+            Payload::Error {
+                code: TEMPORARILY_UNAVAILABLE,
+                ..
+            }
+        ));
+        assert!(network.shards("n3").forwarded.is_empty());
+    }
+}
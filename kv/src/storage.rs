@@ -1,21 +1,116 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 
-#[derive(Debug, Default)]
-pub(crate) struct Messages(pub(crate) HashSet<u64>);
+use crate::{Key, Payload, Value};
+
+const KEY_DOES_NOT_EXIST: u64 = 20;
+pub(crate) const PRECONDITION_FAILED: u64 = 22;
+
+/// Why a request could not be applied, sent back as a Maelstrom error.
+#[derive(Debug)]
+pub(crate) enum Error {
+    KeyDoesNotExist(Key),
+    PreconditionFailed { expected: Value, found: Value },
+}
+
+impl From<Error> for Payload {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::KeyDoesNotExist(key) => Payload::Error {
+                code: KEY_DOES_NOT_EXIST,
+                text: format!("key {} does not exist", key),
+            },
+            Error::PreconditionFailed { expected, found } => Payload::Error {
+                code: PRECONDITION_FAILED,
+                text: format!("expected {}, found {}", expected, found),
+            },
+        }
+    }
+}
 
 #[derive(Debug, Default)]
 pub(crate) struct Storage {
-    pub(crate) messages: Messages,
+    data: HashMap<Key, Value>,
 }
 
 impl Storage {
-    pub(crate) fn add_message(&mut self, message: u64) {
-        if !self.messages.0.contains(&message) {
-            self.messages.0.insert(message);
+    /// Encodes the whole store with bincode. bincode cannot decode arbitrary
+    /// JSON on its own, so each key and value is stored as JSON text.
+    pub(crate) fn snapshot(&self) -> Vec<u8> {
+        let pairs: Vec<(String, String)> = self
+            .data
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        bincode::serialize(&pairs).expect("strings always encode")
+    }
+
+    pub(crate) fn restore(snapshot: &[u8]) -> anyhow::Result<Storage> {
+        let pairs: Vec<(String, String)> = bincode::deserialize(snapshot)?;
+        let data = pairs
+            .iter()
+            .map(|(key, value)| Ok((serde_json::from_str(key)?, serde_json::from_str(value)?)))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Storage { data })
+    }
+
+    /// Applies a client's `read`, `write` or `cas` and returns the reply.
+    pub(crate) fn apply(&mut self, request: Payload) -> Payload {
+        match request {
+            Payload::Read { key } => match self.read(&key) {
+                Ok(value) => Payload::ReadOk { value },
+                Err(error) => error.into(),
+            },
+            Payload::Write { key, value } => {
+                self.write(key, value);
+                Payload::WriteOk {}
+            }
+            Payload::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.cas(key, &from, to, create_if_not_exists) {
+                Ok(()) => Payload::CasOk {},
+                Err(error) => error.into(),
+            },
+            _ => panic!("not a kv request"),
         }
     }
 
-    pub(crate) fn get_messages(&self) -> Vec<u64> {
-        self.messages.0.iter().cloned().collect()
+    fn read(&self, key: &Key) -> Result<Value, Error> {
+        self.data
+            .get(key)
+            .cloned()
+            .ok_or_else(|| Error::KeyDoesNotExist(key.clone()))
+    }
+
+    fn write(&mut self, key: Key, value: Value) {
+        self.data.insert(key, value);
+    }
+
+    /// Sets `key` to `to` if it currently holds `from`. A missing key is
+    /// created when `create_if_not_exists` is set, and an error otherwise.
+    fn cas(
+        &mut self,
+        key: Key,
+        from: &Value,
+        to: Value,
+        create_if_not_exists: bool,
+    ) -> Result<(), Error> {
+        match self.data.get_mut(&key) {
+            Some(current) if current == from => *current = to,
+            Some(current) => {
+                return Err(Error::PreconditionFailed {
+                    expected: from.clone(),
+                    found: current.clone(),
+                })
+            }
+            None if create_if_not_exists => {
+                self.data.insert(key, to);
+            }
+            None => return Err(Error::KeyDoesNotExist(key)),
+        }
+
+        Ok(())
     }
 }
@@ -0,0 +1,336 @@
+use std::{
+    str::FromStr,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// An id as it goes out in `generate_ok`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Id {
+    Integer(u64),
+    Text(String),
+}
+
+pub trait IdGenerator: std::fmt::Debug {
+    fn next_id(&mut self) -> Id;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `<node id>-<counter>`, optionally with a timestamp in between.
+    Counter,
+    /// 64-bit integers: 41 bits of milliseconds, 10 bits of node index and
+    /// 12 bits of sequence.
+    Snowflake,
+    /// RFC 9562 version 7 UUIDs.
+    UuidV7,
+    /// Lexicographically sortable 26-character ids.
+    Ulid,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "counter" => Ok(Format::Counter),
+            "snowflake" => Ok(Format::Snowflake),
+            "uuidv7" => Ok(Format::UuidV7),
+            "ulid" => Ok(Format::Ulid),
+            _ => Err(format!("unknown id format: {}", s)),
+        }
+    }
+}
+
+/// What to do when the wall clock is behind the last id handed out, either
+/// because it was stepped back or because a millisecond's worth of sequence
+/// numbers ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockRegression {
+    /// Block until the clock has caught up. Ids track the wall clock closely,
+    /// but a large step back stalls the node for as long.
+    Wait,
+    /// Keep counting from the last timestamp, moving it forward by a
+    /// millisecond whenever the sequence runs out. Never blocks; ids may run
+    /// ahead of the wall clock until it catches up.
+    Borrow,
+}
+
+impl FromStr for ClockRegression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wait" => Ok(ClockRegression::Wait),
+            "borrow" => Ok(ClockRegression::Borrow),
+            _ => Err(format!("unknown clock regression policy: {}", s)),
+        }
+    }
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system clock is before the unix epoch")
+        .as_millis() as u64
+}
+
+/// Where the timestamped formats read the time, and wait for it.
+pub(crate) trait Clock: std::fmt::Debug {
+    fn now_millis(&self) -> u64;
+    /// Blocks until the clock reads at least `millis` and returns the
+    /// reading.
+    fn wait_until(&self, millis: u64) -> u64;
+}
+
+/// The wall clock.
+#[derive(Debug)]
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_millis(&self) -> u64 {
+        now_millis()
+    }
+
+    fn wait_until(&self, millis: u64) -> u64 {
+        loop {
+            let now = now_millis();
+            if now >= millis {
+                return now;
+            }
+            thread::sleep(Duration::from_millis(millis - now));
+        }
+    }
+}
+
+/// Pairs a millisecond timestamp with a sequence number such that the pairs
+/// it returns strictly increase, whatever the clock does.
+#[derive(Debug)]
+pub(crate) struct Sequencer {
+    last_millis: u64,
+    sequence: u128,
+    max_sequence: u128,
+    on_regression: ClockRegression,
+    clock: Box<dyn Clock>,
+}
+
+impl Sequencer {
+    pub(crate) fn new(max_sequence: u128, on_regression: ClockRegression) -> Sequencer {
+        Sequencer::with_clock(max_sequence, on_regression, Box::new(SystemClock))
+    }
+
+    pub(crate) fn with_clock(
+        max_sequence: u128,
+        on_regression: ClockRegression,
+        clock: Box<dyn Clock>,
+    ) -> Sequencer {
+        Sequencer {
+            last_millis: 0,
+            sequence: 0,
+            max_sequence,
+            on_regression,
+            clock,
+        }
+    }
+
+    /// Next timestamp and sequence number. `first` gives the sequence number
+    /// to start a new millisecond with.
+    pub(crate) fn next(&mut self, first: impl Fn() -> u128) -> (u64, u128) {
+        let mut now = self.clock.now_millis();
+        if now < self.last_millis && self.on_regression == ClockRegression::Wait {
+            now = self.clock.wait_until(self.last_millis);
+        }
+
+        if now > self.last_millis {
+            self.last_millis = now;
+            self.sequence = first();
+        } else if self.sequence < self.max_sequence {
+            self.sequence += 1;
+        } else {
+            self.last_millis = match self.on_regression {
+                ClockRegression::Wait => self.clock.wait_until(self.last_millis + 1),
+                ClockRegression::Borrow => self.last_millis + 1,
+            };
+            self.sequence = first();
+        }
+
+        (self.last_millis, self.sequence)
+    }
+}
+
+/// Ids made of the node id and a counter, optionally with a timestamp so
+/// they stay unique across restarts of a node, since the counter starts
+/// again at zero.
+#[derive(Debug)]
+pub struct Counter {
+    node_id: String,
+    counter: u64,
+    timestamps: bool,
+}
+
+impl Counter {
+    pub fn new(node_id: String, timestamps: bool) -> Counter {
+        Counter {
+            node_id,
+            counter: 0,
+            timestamps,
+        }
+    }
+}
+
+impl IdGenerator for Counter {
+    fn next_id(&mut self) -> Id {
+        let counter = self.counter;
+        self.counter += 1;
+
+        if !self.timestamps {
+            return Id::Text(format!("{}-{}", self.node_id, counter));
+        }
+        Id::Text(format!("{}-{}-{}", self.node_id, now_millis(), counter))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+    /// A clock the test sets by hand. Waiting moves it forward to the time
+    /// waited for, as the wall clock would have got there.
+    #[derive(Debug, Clone)]
+    pub(crate) struct FakeClock(Rc<Cell<u64>>);
+
+    impl FakeClock {
+        pub(crate) fn new(millis: u64) -> FakeClock {
+            FakeClock(Rc::new(Cell::new(millis)))
+        }
+
+        pub(crate) fn set(&self, millis: u64) {
+            self.0.set(millis);
+        }
+
+        pub(crate) fn get(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now_millis(&self) -> u64 {
+            self.0.get()
+        }
+
+        fn wait_until(&self, millis: u64) -> u64 {
+            self.0.set(self.0.get().max(millis));
+            self.0.get()
+        }
+    }
+
+    /// Issues ids while the clock ticks forward, steps back by 50ms and
+    /// stands still long enough to run out of 12-bit sequence numbers.
+    pub(crate) fn issue(generator: &mut dyn IdGenerator, clock: &FakeClock) -> Vec<Id> {
+        (0..12_000)
+            .map(|i| {
+                match i {
+                    2_000 => clock.set(clock.get() - 50),
+                    3_000..=8_000 => {}
+                    _ if i % 7 == 0 => clock.set(clock.get() + 1),
+                    _ => {}
+                }
+                generator.next_id()
+            })
+            .collect()
+    }
+
+    pub(crate) fn assert_sorted(ids: &[Id]) {
+        for pair in ids.windows(2) {
+            let sorted = match pair {
+                [Id::Integer(a), Id::Integer(b)] => a < b,
+                [Id::Text(a), Id::Text(b)] => a < b,
+                _ => false,
+            };
+            assert!(sorted, "{:?} is not before {:?}", pair[0], pair[1]);
+        }
+    }
+
+    fn sequencer(max_sequence: u128, on_regression: ClockRegression) -> (Sequencer, FakeClock) {
+        let clock = FakeClock::new(1_000);
+        let sequencer = Sequencer::with_clock(max_sequence, on_regression, Box::new(clock.clone()));
+        (sequencer, clock)
+    }
+
+    #[test]
+    fn increases_when_the_clock_steps_back() {
+        for on_regression in [ClockRegression::Wait, ClockRegression::Borrow] {
+            let (mut sequencer, clock) = sequencer(4095, on_regression);
+
+            let mut issued = vec![sequencer.next(|| 0), sequencer.next(|| 0)];
+            clock.set(900);
+            issued.extend((0..5).map(|_| sequencer.next(|| 0)));
+
+            assert!(issued.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(issued.last(), Some(&(1_000, 6)), "{:?}", on_regression);
+        }
+    }
+
+    #[test]
+    fn wait_blocks_until_the_clock_catches_up() {
+        let (mut sequencer, clock) = sequencer(4095, ClockRegression::Wait);
+
+        sequencer.next(|| 0);
+        clock.set(900);
+        sequencer.next(|| 0);
+
+        assert_eq!(clock.get(), 1_000);
+    }
+
+    #[test]
+    fn borrow_does_not_touch_the_clock() {
+        let (mut sequencer, clock) = sequencer(4095, ClockRegression::Borrow);
+
+        sequencer.next(|| 0);
+        clock.set(900);
+        sequencer.next(|| 0);
+
+        assert_eq!(clock.get(), 900);
+    }
+
+    #[test]
+    fn exhausting_a_millisecond_moves_to_the_next() {
+        for on_regression in [ClockRegression::Wait, ClockRegression::Borrow] {
+            let (mut sequencer, clock) = sequencer(3, on_regression);
+
+            let issued: Vec<(u64, u128)> = (0..6).map(|_| sequencer.next(|| 0)).collect();
+
+            assert_eq!(
+                issued,
+                [
+                    (1_000, 0),
+                    (1_000, 1),
+                    (1_000, 2),
+                    (1_000, 3),
+                    (1_001, 0),
+                    (1_001, 1)
+                ]
+            );
+            let expected_clock = match on_regression {
+                ClockRegression::Wait => 1_001,
+                ClockRegression::Borrow => 1_000,
+            };
+            assert_eq!(clock.get(), expected_clock);
+        }
+    }
+
+    #[test]
+    fn a_new_millisecond_starts_from_first() {
+        let (mut sequencer, clock) = sequencer(4095, ClockRegression::Borrow);
+
+        sequencer.next(|| 10);
+        assert_eq!(sequencer.next(|| 10), (1_000, 11));
+        clock.set(1_001);
+        assert_eq!(sequencer.next(|| 10), (1_001, 10));
+    }
+}
@@ -0,0 +1,54 @@
+mod generator;
+mod node;
+mod snowflake;
+mod ulid;
+mod uuidv7;
+
+use std::{
+    fmt::Debug,
+    io::{stdin, BufRead, Write},
+};
+
+use node::{Node, UniqueIdNode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Body<Payload> {
+    msg_id: Option<usize>,
+
+    in_reply_to: Option<usize>,
+
+    #[serde(flatten)]
+    payload: Payload,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Message<Payload> {
+    pub src: String,
+    pub dest: String,
+    pub body: Body<Payload>,
+}
+
+fn run<Payload, N>(mut node: N)
+where
+    Payload: Debug + Serialize + DeserializeOwned,
+    N: Node<Payload>,
+{
+    for line in stdin().lock().lines() {
+        let message = serde_json::from_str::<Message<Payload>>(&line.unwrap()).unwrap();
+        let reply = node.step(message);
+        write_to_stdout(reply);
+    }
+}
+
+fn write_to_stdout<Payload: Serialize>(message: Message<Payload>) {
+    let mut stdout = std::io::stdout();
+    let output = serde_json::to_string(&message).unwrap();
+    writeln!(stdout, "{}", output).unwrap();
+    stdout.flush().unwrap();
+}
+
+fn main() {
+    let node = UniqueIdNode::from_env();
+    run(node);
+}
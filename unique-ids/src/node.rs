@@ -0,0 +1,106 @@
+use std::{env, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    generator::{ClockRegression, Counter, Format, Id, IdGenerator},
+    snowflake::Snowflake,
+    ulid::Ulid,
+    uuidv7::UuidV7,
+    Body, Message,
+};
+
+pub trait Node<Payload> {
+    fn step(&mut self, input: Message<Payload>) -> Message<Payload>;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Init {
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+    InitOk {},
+    Generate {},
+    GenerateOk {
+        id: Id,
+    },
+}
+
+/// Hands out ids without talking to any other node, so it stays available
+/// through partitions. Every format gets its uniqueness across nodes from
+/// something only this node has, its id or its position in `node_ids`, or
+/// from enough random bits.
+#[derive(Debug)]
+pub struct UniqueIdNode {
+    format: Format,
+    timestamps: bool,
+    on_regression: ClockRegression,
+    generator: Option<Box<dyn IdGenerator>>,
+}
+
+impl UniqueIdNode {
+    /// Reads `UNIQUE_IDS_FORMAT` (`counter`, `snowflake`, `uuidv7` or `ulid`),
+    /// `UNIQUE_IDS_TIMESTAMP` for the counter format and
+    /// `UNIQUE_IDS_ON_CLOCK_REGRESSION` (`wait` or `borrow`).
+    pub fn from_env() -> UniqueIdNode {
+        UniqueIdNode {
+            format: read_env("UNIQUE_IDS_FORMAT").unwrap_or(Format::Counter),
+            timestamps: read_env("UNIQUE_IDS_TIMESTAMP").unwrap_or(false),
+            on_regression: read_env("UNIQUE_IDS_ON_CLOCK_REGRESSION")
+                .unwrap_or(ClockRegression::Borrow),
+            generator: None,
+        }
+    }
+
+    fn init_generator(&mut self, node_id: String, node_ids: &[String]) {
+        let generator: Box<dyn IdGenerator> = match self.format {
+            Format::Counter => Box::new(Counter::new(node_id, self.timestamps)),
+            Format::Snowflake => Box::new(Snowflake::new(&node_id, node_ids, self.on_regression)),
+            Format::UuidV7 => Box::new(UuidV7::new(self.on_regression)),
+            Format::Ulid => Box::new(Ulid::new(self.on_regression)),
+        };
+        self.generator = Some(generator);
+    }
+}
+
+impl Node<Payload> for UniqueIdNode {
+    fn step(&mut self, input: Message<Payload>) -> Message<Payload> {
+        let payload = match input.body.payload {
+            Payload::Init { node_id, node_ids } => {
+                self.init_generator(node_id, &node_ids);
+                Payload::InitOk {}
+            }
+            Payload::Generate {} => {
+                let generator = self
+                    .generator
+                    .as_mut()
+                    .expect("node is not initialized yet");
+                Payload::GenerateOk {
+                    id: generator.next_id(),
+                }
+            }
+            _ => panic!("invalid type"),
+        };
+
+        Message {
+            src: input.dest,
+            dest: input.src,
+            body: Body {
+                msg_id: input.body.msg_id,
+                in_reply_to: input.body.msg_id,
+                payload,
+            },
+        }
+    }
+}
+
+fn read_env<T: FromStr>(key: &str) -> Option<T> {
+    let value = env::var(key).ok()?;
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => panic!("invalid value for {}: {}", key, value),
+    }
+}
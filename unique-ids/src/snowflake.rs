@@ -0,0 +1,89 @@
+use crate::generator::{ClockRegression, Id, IdGenerator, Sequencer};
+
+/// Twitter's snowflake epoch, 2010-11-04T01:42:54.657Z.
+const EPOCH_MILLIS: u64 = 1_288_834_974_657;
+const NODE_BITS: u32 = 10;
+const SEQUENCE_BITS: u32 = 12;
+const TIMESTAMP_BITS: u32 = 41;
+
+/// 64-bit ids laid out as `timestamp | node index | sequence`.
+///
+/// The node index is the node's position in the `node_ids` list from `init`,
+/// which every node sees in the same order, so no two nodes share one and up
+/// to 1024 nodes fit.
+#[derive(Debug)]
+pub struct Snowflake {
+    node_index: u64,
+    sequencer: Sequencer,
+}
+
+impl Snowflake {
+    pub fn new(node_id: &str, node_ids: &[String], on_regression: ClockRegression) -> Snowflake {
+        let node_index = node_ids
+            .iter()
+            .position(|id| id == node_id)
+            .expect("node is not in node_ids") as u64;
+        assert!(
+            node_index < 1 << NODE_BITS,
+            "snowflake ids fit at most {} nodes",
+            1 << NODE_BITS
+        );
+
+        Snowflake {
+            node_index,
+            sequencer: Sequencer::new((1 << SEQUENCE_BITS) - 1, on_regression),
+        }
+    }
+}
+
+impl IdGenerator for Snowflake {
+    fn next_id(&mut self) -> Id {
+        let (millis, sequence) = self.sequencer.next(|| 0);
+        let timestamp = millis.saturating_sub(EPOCH_MILLIS) & ((1 << TIMESTAMP_BITS) - 1);
+
+        Id::Integer(
+            timestamp << (NODE_BITS + SEQUENCE_BITS)
+                | self.node_index << SEQUENCE_BITS
+                | sequence as u64,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{
+        tests::{assert_sorted, issue, FakeClock},
+        Sequencer,
+    };
+
+    #[test]
+    fn ids_sort_in_issue_order() {
+        for on_regression in [ClockRegression::Wait, ClockRegression::Borrow] {
+            let clock = FakeClock::new(EPOCH_MILLIS + 1_000);
+            let mut snowflake = Snowflake {
+                node_index: 3,
+                sequencer: Sequencer::with_clock(
+                    (1 << SEQUENCE_BITS) - 1,
+                    on_regression,
+                    Box::new(clock.clone()),
+                ),
+            };
+
+            assert_sorted(&issue(&mut snowflake, &clock));
+        }
+    }
+
+    #[test]
+    fn node_index_separates_nodes() {
+        let node_ids = ["n0".to_string(), "n1".to_string()];
+        let mut a = Snowflake::new("n0", &node_ids, ClockRegression::Borrow);
+        let mut b = Snowflake::new("n1", &node_ids, ClockRegression::Borrow);
+
+        let (Id::Integer(a), Id::Integer(b)) = (a.next_id(), b.next_id()) else {
+            panic!("snowflake ids are integers");
+        };
+        assert_eq!(a >> SEQUENCE_BITS & 0x3ff, 0);
+        assert_eq!(b >> SEQUENCE_BITS & 0x3ff, 1);
+    }
+}
@@ -0,0 +1,65 @@
+use rand::Rng;
+
+use crate::generator::{ClockRegression, Id, IdGenerator, Sequencer};
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const RANDOM_BITS: u32 = 80;
+
+/// ULIDs: 48 bits of unix milliseconds and 80 random bits, written as 26
+/// Crockford base32 characters.
+///
+/// Within a millisecond the random part is incremented instead of redrawn,
+/// as in the spec's monotonic mode, so ids from one node sort in the order
+/// they were handed out.
+#[derive(Debug)]
+pub struct Ulid {
+    sequencer: Sequencer,
+}
+
+impl Ulid {
+    pub fn new(on_regression: ClockRegression) -> Ulid {
+        Ulid {
+            sequencer: Sequencer::new((1 << RANDOM_BITS) - 1, on_regression),
+        }
+    }
+}
+
+impl IdGenerator for Ulid {
+    fn next_id(&mut self) -> Id {
+        // leave headroom so a burst within one millisecond does not overflow
+        let (millis, random) = self
+            .sequencer
+            .next(|| rand::thread_rng().gen_range(0..1 << (RANDOM_BITS - 1)));
+        let ulid = (millis as u128 & 0xffff_ffff_ffff) << RANDOM_BITS | random;
+
+        let text = (0..26)
+            .map(|i| CROCKFORD[(ulid >> (125 - 5 * i) & 0x1f) as usize] as char)
+            .collect();
+        Id::Text(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{
+        tests::{assert_sorted, issue, FakeClock},
+        Sequencer,
+    };
+
+    #[test]
+    fn ids_sort_in_issue_order() {
+        for on_regression in [ClockRegression::Wait, ClockRegression::Borrow] {
+            let clock = FakeClock::new(1_700_000_000_000);
+            let mut ulid = Ulid {
+                sequencer: Sequencer::with_clock(
+                    (1 << RANDOM_BITS) - 1,
+                    on_regression,
+                    Box::new(clock.clone()),
+                ),
+            };
+
+            assert_sorted(&issue(&mut ulid, &clock));
+        }
+    }
+}
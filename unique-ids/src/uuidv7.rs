@@ -0,0 +1,81 @@
+use rand::Rng;
+
+use crate::generator::{ClockRegression, Id, IdGenerator, Sequencer};
+
+/// RFC 9562 version 7 UUIDs: 48 bits of unix milliseconds, the 12-bit
+/// `rand_a` field used as a counter within the millisecond (the RFC's
+/// "fixed bit-length dedicated counter" method) and 62 random bits.
+///
+/// The counter keeps ids from one node monotonic; uniqueness across nodes
+/// comes from the random bits.
+#[derive(Debug)]
+pub struct UuidV7 {
+    sequencer: Sequencer,
+}
+
+impl UuidV7 {
+    pub fn new(on_regression: ClockRegression) -> UuidV7 {
+        UuidV7 {
+            sequencer: Sequencer::new((1 << 12) - 1, on_regression),
+        }
+    }
+}
+
+impl IdGenerator for UuidV7 {
+    fn next_id(&mut self) -> Id {
+        let (millis, counter) = self.sequencer.next(|| 0);
+        let random: u64 = rand::thread_rng().gen();
+
+        let uuid: u128 = (millis as u128 & 0xffff_ffff_ffff) << 80
+            | 0x7 << 76
+            | counter << 64
+            | 0b10 << 62
+            | (random & 0x3fff_ffff_ffff_ffff) as u128;
+
+        let hex = format!("{:032x}", uuid);
+        Id::Text(format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{
+        tests::{assert_sorted, issue, FakeClock},
+        Sequencer,
+    };
+
+    #[test]
+    fn ids_sort_in_issue_order() {
+        for on_regression in [ClockRegression::Wait, ClockRegression::Borrow] {
+            let clock = FakeClock::new(1_700_000_000_000);
+            let mut uuid = UuidV7 {
+                sequencer: Sequencer::with_clock(
+                    (1 << 12) - 1,
+                    on_regression,
+                    Box::new(clock.clone()),
+                ),
+            };
+
+            assert_sorted(&issue(&mut uuid, &clock));
+        }
+    }
+
+    #[test]
+    fn ids_carry_the_version_and_variant() {
+        let Id::Text(uuid) = UuidV7::new(ClockRegression::Borrow).next_id() else {
+            panic!("uuids are text");
+        };
+
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "7");
+        assert!(matches!(&uuid[19..20], "8" | "9" | "a" | "b"));
+    }
+}